@@ -6,7 +6,13 @@
 #[macro_use]
 extern crate rcore_console;
 
-static mut UART: usize = 0;
+use core::{ops::Range, ptr::addr_of};
+use uart::Ns16550a;
+
+/// 串口的波特率。
+const BAUD: u32 = 115200;
+
+static mut UART: Option<Ns16550a> = None;
 static mut TEST: usize = 0;
 
 linker::boot0!(rust_main; stack = 4096 * 2);
@@ -15,9 +21,12 @@ extern "C" fn rust_main(_hartid: usize, dtb_ptr: usize) -> ! {
     // 清零 .bss
     unsafe { linker::zero_bss() };
     // 从设备树中解析出串口、测试设备的地址以及机器型号
-    let machine = MachineInfo::from_dtb(dtb_ptr);
+    // 没有可用的设备树就找不到串口和测试设备，只能停住
+    let machine = MachineInfo::from_dtb(dtb_ptr).unwrap_or_else(|| shutdown());
     unsafe {
-        UART = machine.uart;
+        let dev = Ns16550a::new(machine.uart, machine.uart_shift, machine.uart_width);
+        dev.init(machine.uart_clock, BAUD);
+        UART = Some(dev);
         TEST = machine.test;
     }
     // 初始化 `console`
@@ -31,8 +40,11 @@ ___       __ __ _
  | || |\/__)|__)|
 -------/---------
 machine: {machine}
+dtb    : {:#x}..{:#x}
 ",
-        machine = machine.model
+        machine.dtb.start,
+        machine.dtb.end,
+        machine = machine.model,
     );
     shutdown()
 }
@@ -47,42 +59,81 @@ struct Console;
 
 impl rcore_console::Console for Console {
     fn put_char(&self, c: u8) {
-        unsafe { (UART as *mut u8).write_volatile(c) };
+        if let Some(uart) = unsafe { &*addr_of!(UART) } {
+            uart.put_char(c);
+        }
     }
 }
 
 fn shutdown() -> ! {
-    unsafe { &*(TEST as *const sifive_test_device::SifiveTestDevice) }.pass()
+    match unsafe { TEST } {
+        // 没有测试设备时无法关机，停在这里
+        0 => loop {
+            unsafe { core::arch::asm!("wfi") };
+        },
+        test => unsafe { &*(test as *const sifive_test_device::SifiveTestDevice) }.pass(),
+    }
 }
 
 struct MachineInfo {
     model: &'static str,
     uart: usize,
+    uart_shift: u32,
+    uart_width: u32,
+    uart_clock: u32,
     test: usize,
+    mem: Range<usize>,
+    /// 设备树最终所在的范围，可能已被复制到固件缓冲区。
+    dtb: Range<usize>,
 }
 
 impl MachineInfo {
-    fn from_dtb(dtb_ptr: usize) -> Self {
-        use core::mem::MaybeUninit;
+    /// 检查设备树的位置，必要时复制到固件缓冲区，再解析。
+    ///
+    /// 设备树不存在、放不进缓冲区或无法解析时返回 `None`。
+    fn from_dtb(dtb_ptr: usize) -> Option<Self> {
+        let dtb = locate(dtb_ptr)?;
+        // 未对齐或压在固件上的设备树先搬走
+        let ptr = if dtb.start % 8 == 0 && !overlaps(&linker::image(), &dtb) {
+            dtb.start
+        } else {
+            relocate(&dtb)?
+        };
+        let mut ans = Self::walk(ptr)?;
+        ans.dtb = ptr..ptr + dtb.len();
+        // 引导程序可能把设备树放在内存以外，比如只读存储器里
+        if ptr == dtb.start && !(ans.mem.start <= dtb.start && dtb.end <= ans.mem.end) {
+            ans.dtb.start = relocate(&dtb)?;
+            ans.dtb.end = ans.dtb.start + dtb.len();
+        }
+        Some(ans)
+    }
+
+    fn walk(ptr: usize) -> Option<Self> {
         use dtb_walker::{Dtb, DtbObj, HeaderError as E, Property, Str, WalkOperation::*};
 
-        let ans = MaybeUninit::<Self>::uninit();
-        let mut ans = unsafe { ans.assume_init() };
+        let mut ans = Self {
+            model: "",
+            uart: 0,
+            uart_shift: 0,
+            uart_width: 1,
+            uart_clock: 0,
+            test: 0,
+            mem: 0..0,
+            dtb: 0..0,
+        };
         let dtb = unsafe {
-            Dtb::from_raw_parts_filtered(dtb_ptr as _, |e| {
-                matches!(e, E::Misaligned(4) | E::LastCompVersion(_))
-            })
+            Dtb::from_raw_parts_filtered(ptr as _, |e| matches!(e, E::LastCompVersion(_)))
         }
-        .unwrap();
+        .ok()?;
         dtb.walk(|ctx, obj| match obj {
             DtbObj::SubNode { name } => {
                 let current = ctx.name();
-                if ctx.is_root() && name == Str::from("soc") {
-                    StepInto
-                } else if current == Str::from("soc")
-                    && ["uart", "serial", "test", "clint"]
-                        .iter()
-                        .any(|pre| name.starts_with(pre))
+                if (ctx.is_root() && (name == Str::from("soc") || name.starts_with("memory")))
+                    || (current == Str::from("soc")
+                        && ["uart", "serial", "test", "clint"]
+                            .iter()
+                            .any(|pre| name.starts_with(pre)))
                 {
                     StepInto
                 } else {
@@ -103,16 +154,85 @@ impl MachineInfo {
                 let node = ctx.name();
                 if node.starts_with("uart") || node.starts_with("serial") {
                     ans.uart = reg.next().unwrap().start;
-                    StepOut
+                    StepOver
                 } else if node.starts_with("test") {
                     ans.test = reg.next().unwrap().start;
                     StepOut
+                } else if node.starts_with("memory") {
+                    ans.mem = reg.next().unwrap();
+                    StepOut
                 } else {
                     StepOver
                 }
             }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name().starts_with("uart") || ctx.name().starts_with("serial") =>
+            {
+                let cell = value
+                    .get(..4)
+                    .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                if name == Str::from("clock-frequency") {
+                    ans.uart_clock = cell;
+                } else if name == Str::from("reg-shift") {
+                    ans.uart_shift = cell;
+                } else if name == Str::from("reg-io-width") {
+                    ans.uart_width = cell;
+                }
+                StepOver
+            }
             DtbObj::Property(_) => StepOver,
         });
-        ans
+        Some(ans)
+    }
+}
+
+/// 固件自有的设备树缓冲区容量。
+const DTB_BUFFER_SIZE: usize = 64 << 10;
+
+/// 设备树头部的魔数。
+const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// 设备树头部的长度。
+const FDT_HEADER_LEN: usize = 40;
+
+/// 检查设备树头部，返回设备树占用的范围。
+///
+/// 引导程序传来的地址未必对齐，所以头部按字节读取。
+fn locate(dtb_ptr: usize) -> Option<Range<usize>> {
+    if dtb_ptr == 0 || read_be32(dtb_ptr) != FDT_MAGIC {
+        return None;
     }
+    // 头部第 4~7 字节是大端序的设备树总长度
+    let size = read_be32(dtb_ptr + 4) as usize;
+    (size >= FDT_HEADER_LEN).then_some(dtb_ptr..dtb_ptr + size)
+}
+
+/// 设备树要求 8 字节对齐，但引导程序未必遵守。
+///
+/// 把设备树复制到一个对齐的缓冲区里，返回新的地址，放不下时返回 `None`。
+fn relocate(dtb: &Range<usize>) -> Option<usize> {
+    #[repr(C, align(8))]
+    struct Buffer([u8; DTB_BUFFER_SIZE]);
+    static mut BUFFER: Buffer = Buffer([0; DTB_BUFFER_SIZE]);
+
+    if dtb.len() > DTB_BUFFER_SIZE {
+        return None;
+    }
+    unsafe {
+        let dst = core::ptr::addr_of_mut!(BUFFER) as *mut u8;
+        // 原位置可能与缓冲区重叠，不能用 `copy_nonoverlapping`
+        core::ptr::copy(dtb.start as *const u8, dst, dtb.len());
+        Some(dst as usize)
+    }
+}
+
+#[inline]
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// 按大端序读取可能未对齐的 32 位数。
+#[inline]
+fn read_be32(addr: usize) -> u32 {
+    u32::from_be_bytes(unsafe { (addr as *const [u8; 4]).read_unaligned() })
 }
@@ -3,39 +3,29 @@
 #![feature(naked_functions, asm_const)]
 #![deny(warnings)]
 
-#[macro_use]
-extern crate rcore_console;
+/// 内核入口，qemu 将 `-kernel` 指定的内核加载到这里。
+const KERNEL_ENTRY: usize = 0x8020_0000;
+/// 为内核镜像保留的长度，引导程序传来的设备树在这个范围内时要搬走。
+const KERNEL_SIZE: usize = 32 << 20;
 
-static mut UART: usize = 0;
 static mut TEST: usize = 0;
 
+static LEGACY: dispatch::Legacy = dispatch::Legacy::new(shutdown);
+
 linker::boot0!(rust_main; stack = 4096 * 2);
 
 extern "C" fn rust_main(hartid: usize, dtb_ptr: usize) -> ! {
     unsafe { linker::zero_bss() };
-    let machine = machine_info::MachineInfo::from_dtb(dtb_ptr);
-    unsafe {
-        UART = machine.uart.start;
-        TEST = machine.test.start;
+    let machine = dispatch::boot(hartid, dtb_ptr, KERNEL_ENTRY..KERNEL_ENTRY + KERNEL_SIZE);
+    unsafe { TEST = machine.test.start };
+    LEGACY.init(&machine);
+    dispatch::init();
+    // 内存是清零的，没有加载内核时入口处是 0
+    if unsafe { (KERNEL_ENTRY as *const u32).read_volatile() } == 0 {
+        rcore_console::log::warn!("no kernel at {KERNEL_ENTRY:#x}");
+        shutdown()
     }
-    rcore_console::init_console(&Console);
-    rcore_console::set_log_level(option_env!("LOG"));
-    println!(
-        r"
-___       __ __ _
- | . _   (_ |__)|
- | || |\/__)|__)|
--------/---------
-machine: {machine}
-memory: {mem:#x?}
-boot hart: {hartid}
-dtb region: {dtb:#x?}
-",
-        machine = machine.model,
-        mem = machine.mem,
-        dtb = machine.dtb,
-    );
-    shutdown()
+    unsafe { dispatch::enter_supervisor(hartid, machine.dtb.start, KERNEL_ENTRY) }
 }
 
 #[panic_handler]
@@ -44,14 +34,10 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     loop {}
 }
 
-struct Console;
-
-impl rcore_console::Console for Console {
-    fn put_char(&self, c: u8) {
-        unsafe { (UART as *mut u8).write_volatile(c) };
-    }
-}
-
 fn shutdown() -> ! {
-    unsafe { &*(TEST as *const sifive_test_device::SifiveTestDevice) }.pass()
+    match unsafe { TEST } {
+        // 没有测试设备，比如 spike，通过 HTIF 退出
+        0 => console::Htif::exit(0),
+        test => unsafe { &*(test as *const sifive_test_device::SifiveTestDevice) }.pass(),
+    }
 }
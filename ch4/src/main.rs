@@ -3,15 +3,43 @@
 #![feature(naked_functions, asm_const)]
 #![deny(warnings)]
 
-linker::boot0!(rust_main; stack = 4096);
+/// 内核入口，qemu 将 `-kernel` 指定的内核加载到这里。
+const KERNEL_ENTRY: usize = 0x8020_0000;
+/// 为内核镜像保留的长度，引导程序传来的设备树在这个范围内时要搬走。
+const KERNEL_SIZE: usize = 32 << 20;
 
-extern "C" fn rust_main() -> ! {
-    // 清零 .bss
+static mut TEST: usize = 0;
+
+static LEGACY: dispatch::Legacy = dispatch::Legacy::new(shutdown);
+static BASE: dispatch::Base = dispatch::Base::new(env!("CARGO_PKG_VERSION"));
+
+linker::boot0!(rust_main; stack = 4096 * 2);
+
+extern "C" fn rust_main(hartid: usize, dtb_ptr: usize) -> ! {
     unsafe { linker::zero_bss() };
-    unsafe { &*(0x10_0000 as *const sifive_test_device::SifiveTestDevice) }.pass()
+    let machine = dispatch::boot(hartid, dtb_ptr, KERNEL_ENTRY..KERNEL_ENTRY + KERNEL_SIZE);
+    unsafe { TEST = machine.test.start };
+    LEGACY.init(&machine);
+    dispatch::register(sbi_spec::base::EID_BASE, &BASE);
+    dispatch::init();
+    // 内存是清零的，没有加载内核时入口处是 0
+    if unsafe { (KERNEL_ENTRY as *const u32).read_volatile() } == 0 {
+        rcore_console::log::warn!("no kernel at {KERNEL_ENTRY:#x}");
+        shutdown()
+    }
+    unsafe { dispatch::enter_supervisor(hartid, machine.dtb.start, KERNEL_ENTRY) }
 }
 
 #[panic_handler]
-fn panic(_: &core::panic::PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rcore_console::log::error!("{info}");
     loop {}
 }
+
+fn shutdown() -> ! {
+    match unsafe { TEST } {
+        // 没有测试设备，比如 spike，通过 HTIF 退出
+        0 => console::Htif::exit(0),
+        test => unsafe { &*(test as *const sifive_test_device::SifiveTestDevice) }.pass(),
+    }
+}
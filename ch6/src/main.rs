@@ -3,15 +3,79 @@
 #![feature(naked_functions, asm_const)]
 #![deny(warnings)]
 
-linker::boot0!(rust_main; stack = 4096);
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use machine_info::{MachineInfo, MAX_HARTS};
 
-extern "C" fn rust_main() -> ! {
-    // 清零 .bss
-    unsafe { linker::zero_bss() };
-    unsafe { &*(0x10_0000 as *const sifive_test_device::SifiveTestDevice) }.pass()
+/// 内核入口，qemu 将 `-kernel` 指定的内核加载到这里。
+const KERNEL_ENTRY: usize = 0x8020_0000;
+/// 为内核镜像保留的长度，引导程序传来的设备树在这个范围内时要搬走。
+const KERNEL_SIZE: usize = 32 << 20;
+
+// 其他硬件线程可能在 .bss 清零之前读取这两个变量，所以放在 .data
+/// 第一个取得它的硬件线程负责初始化。
+#[link_section = ".data"]
+static LOTTERY: AtomicBool = AtomicBool::new(true);
+/// 初始化完成后置位，其他硬件线程才能继续。
+#[link_section = ".data"]
+static READY: AtomicBool = AtomicBool::new(false);
+
+static mut TEST: usize = 0;
+static mut DTB: usize = 0;
+
+static LEGACY: dispatch::Legacy = dispatch::Legacy::new(shutdown);
+static BASE: dispatch::Base = dispatch::Base::new(env!("CARGO_PKG_VERSION"));
+static TIME: dispatch::Time = dispatch::Time;
+static IPI: dispatch::Ipi = dispatch::Ipi;
+static SRST: dispatch::Srst = dispatch::Srst;
+
+linker::boot0!(rust_main; stack = 4096 * 2; harts = MAX_HARTS);
+
+extern "C" fn rust_main(hartid: usize, dtb_ptr: usize) -> ! {
+    if LOTTERY.swap(false, Ordering::AcqRel) {
+        unsafe { linker::zero_bss() };
+        let machine = boot(hartid, dtb_ptr);
+        unsafe { DTB = machine.dtb.start };
+        READY.store(true, Ordering::Release);
+    } else {
+        while !READY.load(Ordering::Acquire) {
+            spin_loop();
+        }
+    }
+    dispatch::init();
+    // 内存是清零的，没有加载内核时入口处是 0
+    if unsafe { (KERNEL_ENTRY as *const u32).read_volatile() } == 0 {
+        rcore_console::log::warn!("no kernel at {KERNEL_ENTRY:#x}");
+        shutdown()
+    }
+    // 没有 HSM，所有硬件线程一起进入内核
+    unsafe { dispatch::enter_supervisor(hartid, DTB, KERNEL_ENTRY) }
+}
+
+/// 由引导硬件线程完成的初始化。
+fn boot(hartid: usize, dtb_ptr: usize) -> MachineInfo {
+    let machine = dispatch::boot(hartid, dtb_ptr, KERNEL_ENTRY..KERNEL_ENTRY + KERNEL_SIZE);
+    unsafe { TEST = machine.test.start };
+    LEGACY.init(&machine);
+    TIME.init(&machine);
+    IPI.init(&machine);
+    SRST.init(&machine);
+    dispatch::register(sbi_spec::base::EID_BASE, &BASE);
+    machine
 }
 
 #[panic_handler]
-fn panic(_: &core::panic::PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    rcore_console::log::error!("{info}");
     loop {}
 }
+
+fn shutdown() -> ! {
+    match unsafe { TEST } {
+        // 没有测试设备，比如 spike，通过 HTIF 退出
+        0 => console::Htif::exit(0),
+        test => unsafe { &*(test as *const sifive_test_device::SifiveTestDevice) }.pass(),
+    }
+}
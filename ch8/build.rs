@@ -3,5 +3,26 @@ fn main() {
     std::fs::write(&ld, linker::SCRIPT).unwrap();
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=LOG");
+    // 切换分支时 HEAD 变化，提交时 HEAD 指向的引用变化，引用被打包后则是 packed-refs 变化
+    let head = git(&["symbolic-ref", "-q", "HEAD"]);
+    for name in ["HEAD", "packed-refs"].into_iter().chain(head.as_deref()) {
+        if let Some(path) = git(&["rev-parse", "--git-path", name]) {
+            if std::path::Path::new(&path).exists() {
+                println!("cargo:rerun-if-changed={path}");
+            }
+        }
+    }
+    let hash = git(&["rev-parse", "--short", "HEAD"]).unwrap_or_else(|| "unknown".into());
+    println!("cargo:rustc-env=GIT_HASH={hash}");
     println!("cargo:rustc-link-arg=-T{}", ld.display());
 }
+
+/// 执行 git 命令，返回去掉首尾空白的输出，失败时返回 `None`。
+fn git(args: &[&str]) -> Option<String> {
+    std::process::Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
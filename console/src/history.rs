@@ -0,0 +1,52 @@
+//! 固件日志的历史。
+//!
+//! 带前缀输出的每一行同时追加到一个环形缓冲，转发的 S 态输出不记录。
+//! 位置是启动以来记录的总字节数，缓冲满时覆盖最早的数据，所以只保留最近 [`HISTORY_CAP`] 字节。
+
+use core::ops::Range;
+use spin::Mutex;
+
+/// 保留的历史字节数。
+pub const HISTORY_CAP: usize = 4096;
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    end: 0,
+    buf: [0; HISTORY_CAP],
+});
+
+struct History {
+    end: usize,
+    buf: [u8; HISTORY_CAP],
+}
+
+/// 追加到历史。
+pub(crate) fn push(bytes: &[u8]) {
+    let mut history = HISTORY.lock();
+    for &c in bytes {
+        let i = history.end % HISTORY_CAP;
+        history.buf[i] = c;
+        history.end += 1;
+    }
+}
+
+/// 仍然保留的历史的位置。
+pub fn range() -> Range<usize> {
+    let end = HISTORY.lock().end;
+    end.saturating_sub(HISTORY_CAP)..end
+}
+
+/// 从位置 `pos` 开始读取历史，返回读取的字节数。
+///
+/// `pos` 已被覆盖或还没有记录时返回 `None`。
+pub fn read(pos: usize, buf: &mut [u8]) -> Option<usize> {
+    let history = HISTORY.lock();
+    let start = history.end.saturating_sub(HISTORY_CAP);
+    if !(start..=history.end).contains(&pos) {
+        return None;
+    }
+    let n = buf.len().min(history.end - pos);
+    for (i, b) in buf[..n].iter_mut().enumerate() {
+        *b = history.buf[(pos + i) % HISTORY_CAP];
+    }
+    Some(n)
+}
@@ -0,0 +1,83 @@
+//! HTIF（Host-Target Interface），spike 与宿主机交互的接口。
+//!
+//! 目标机把命令写入 `tohost`，宿主机处理后清零 `tohost`，并把回应写入 `fromhost`。
+
+use crate::ConsoleDevice;
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicI32, Ordering::Relaxed},
+};
+
+// 宿主机通过符号名找到这两个变量，不能改名。
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut tohost: u64 = 0;
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut fromhost: u64 = 0;
+
+const DEV_CONSOLE: u64 = 1;
+const CMD_GETC: u64 = 0;
+const CMD_PUTC: u64 = 1;
+
+/// 控制台接收缓冲。
+///
+/// 0 表示没有字节，-1 表示读请求已发出但还没有回应，正数是收到的字节加 1。
+static CONSOLE_BUF: AtomicI32 = AtomicI32::new(0);
+
+/// HTIF 控制台。
+pub struct Htif;
+
+impl ConsoleDevice for Htif {
+    fn put_char(&self, c: u8) {
+        set_tohost(DEV_CONSOLE, CMD_PUTC, c as _);
+    }
+
+    fn get_char(&self) -> Option<u8> {
+        check_fromhost();
+        let ch = CONSOLE_BUF.load(Relaxed);
+        // 上一个读请求已完成，发出下一个
+        if ch >= 0 {
+            CONSOLE_BUF.store(-1, Relaxed);
+            set_tohost(DEV_CONSOLE, CMD_GETC, 0);
+        }
+        match ch {
+            1.. => Some((ch - 1) as u8),
+            _ => None,
+        }
+    }
+}
+
+impl Htif {
+    /// 通知宿主机以 `code` 退出。
+    ///
+    /// 宿主机不是 spike 时没有回应，停在 `wfi`。
+    pub fn exit(code: u32) -> ! {
+        // 设备 0 的命令 0 是系统调用，最低位为 1 时表示退出，退出码在其余位
+        set_tohost(0, 0, (code as u64) << 1 | 1);
+        loop {
+            unsafe { core::arch::asm!("wfi") };
+        }
+    }
+}
+
+/// 等待宿主机取走上一条命令，然后写入新命令。
+fn set_tohost(dev: u64, cmd: u64, data: u64) {
+    while unsafe { addr_of!(tohost).read_volatile() } != 0 {
+        check_fromhost();
+    }
+    let val = dev << 56 | cmd << 48 | data & 0xffff_ffff_ffff;
+    unsafe { addr_of_mut!(tohost).write_volatile(val) };
+}
+
+/// 处理宿主机的回应。
+fn check_fromhost() {
+    let val = unsafe { addr_of!(fromhost).read_volatile() };
+    if val == 0 {
+        return;
+    }
+    unsafe { addr_of_mut!(fromhost).write_volatile(0) };
+    if val >> 56 == DEV_CONSOLE && (val >> 48) & 0xff == CMD_GETC {
+        CONSOLE_BUF.store(1 + (val as u8) as i32, Relaxed);
+    }
+}
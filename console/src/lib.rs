@@ -0,0 +1,191 @@
+//! 这个项目为多种控制台设备提供统一的抽象，并适配 `rcore_console`。
+//!
+//! 控制台后端在启动时根据 [`MachineInfo`] 选择：
+//!
+//! - ns16550a 兼容串口，用于 qemu virt；
+//! - `sifive,uart0`，用于 qemu sifive_u；
+//! - HTIF，用于 spike。
+//!
+//! 多个硬件线程的输出按行加锁，并带有时间戳和硬件线程号前缀。
+//!
+//! 有 PLIC 时，输入由串口接收中断搬进环形缓冲，见 [`rx`]。
+//!
+//! 固件自己输出的行另外记录在 [`history`] 中。
+
+#![no_std]
+#![deny(warnings, missing_docs)]
+
+pub mod history;
+mod htif;
+mod line;
+pub mod rx;
+
+pub use htif::Htif;
+pub use line::write_raw;
+pub use machine_info::MAX_HARTS;
+
+use core::ptr::addr_of;
+use machine_info::{MachineInfo, UartModel};
+use uart::{Ns16550a, SifiveUart};
+
+/// 串口的默认波特率。
+const BAUD: u32 = 115200;
+
+/// 控制台设备。
+pub trait ConsoleDevice: Sync {
+    /// 发送一个字节，必要时等待设备就绪。
+    fn put_char(&self, c: u8);
+
+    /// 非阻塞地接收一个字节。
+    fn get_char(&self) -> Option<u8>;
+
+    /// 开关接收中断，设备不支持时返回 `false`。
+    #[inline]
+    fn set_rx_interrupt(&self, enable: bool) -> bool {
+        let _ = enable;
+        false
+    }
+}
+
+impl ConsoleDevice for Ns16550a {
+    #[inline]
+    fn put_char(&self, c: u8) {
+        Ns16550a::put_char(self, c)
+    }
+
+    #[inline]
+    fn get_char(&self) -> Option<u8> {
+        Ns16550a::get_char(self)
+    }
+
+    #[inline]
+    fn set_rx_interrupt(&self, enable: bool) -> bool {
+        Ns16550a::set_rx_interrupt(self, enable);
+        true
+    }
+}
+
+impl ConsoleDevice for SifiveUart {
+    #[inline]
+    fn put_char(&self, c: u8) {
+        SifiveUart::put_char(self, c)
+    }
+
+    #[inline]
+    fn get_char(&self) -> Option<u8> {
+        SifiveUart::get_char(self)
+    }
+
+    #[inline]
+    fn set_rx_interrupt(&self, enable: bool) -> bool {
+        SifiveUart::set_rx_interrupt(self, enable);
+        true
+    }
+}
+
+/// 启动时选择的控制台后端。
+pub enum Device {
+    /// 没有可用的控制台，输出被丢弃。
+    None,
+    /// ns16550a 兼容串口。
+    Ns16550a(Ns16550a),
+    /// SiFive 串口。
+    SifiveUart(SifiveUart),
+    /// HTIF 控制台。
+    Htif(Htif),
+}
+
+impl Device {
+    /// 根据机器信息选择并初始化控制台后端。
+    ///
+    /// 有串口时优先使用串口。
+    ///
+    /// # Safety
+    ///
+    /// 机器信息必须正确描述了设备。
+    pub unsafe fn from_machine(machine: &MachineInfo) -> Self {
+        let uart = &machine.uart;
+        if !uart.reg.is_empty() {
+            match uart.model {
+                UartModel::Ns16550a => {
+                    let dev = Ns16550a::new(uart.reg.start, uart.reg_shift, uart.reg_io_width);
+                    dev.init(uart.clock_frequency, BAUD);
+                    Self::Ns16550a(dev)
+                }
+                UartModel::SifiveUart0 => {
+                    let dev = SifiveUart::new(uart.reg.start);
+                    dev.init(uart.clock_frequency, BAUD);
+                    Self::SifiveUart(dev)
+                }
+            }
+        } else if machine.htif {
+            Self::Htif(Htif)
+        } else {
+            Self::None
+        }
+    }
+}
+
+impl ConsoleDevice for Device {
+    #[inline]
+    fn put_char(&self, c: u8) {
+        match self {
+            Self::None => {}
+            Self::Ns16550a(dev) => dev.put_char(c),
+            Self::SifiveUart(dev) => dev.put_char(c),
+            Self::Htif(dev) => dev.put_char(c),
+        }
+    }
+
+    #[inline]
+    fn get_char(&self) -> Option<u8> {
+        match self {
+            Self::None => None,
+            Self::Ns16550a(dev) => dev.get_char(),
+            Self::SifiveUart(dev) => dev.get_char(),
+            Self::Htif(dev) => dev.get_char(),
+        }
+    }
+
+    #[inline]
+    fn set_rx_interrupt(&self, enable: bool) -> bool {
+        match self {
+            Self::None => false,
+            Self::Ns16550a(dev) => ConsoleDevice::set_rx_interrupt(dev, enable),
+            Self::SifiveUart(dev) => ConsoleDevice::set_rx_interrupt(dev, enable),
+            Self::Htif(dev) => dev.set_rx_interrupt(enable),
+        }
+    }
+}
+
+static mut DEVICE: Device = Device::None;
+
+/// 根据机器信息选择控制台后端，并设置为 `rcore_console` 的输出。
+///
+/// 必须在单核环境中调用一次。
+pub fn init(machine: &MachineInfo) {
+    unsafe { DEVICE = Device::from_machine(machine) };
+    line::set_timer(&machine.clint, machine.timebase_frequency);
+    rcore_console::init_console(&Console);
+}
+
+/// 当前的控制台后端。
+#[inline]
+pub fn device() -> &'static Device {
+    unsafe { &*addr_of!(DEVICE) }
+}
+
+/// 适配 `rcore_console` 的控制台，按行加锁输出到 [`device`]。
+pub struct Console;
+
+impl rcore_console::Console for Console {
+    #[inline]
+    fn put_char(&self, c: u8) {
+        line::put_bytes(&[c]);
+    }
+
+    #[inline]
+    fn put_str(&self, s: &str) {
+        line::put_bytes(s.as_bytes());
+    }
+}
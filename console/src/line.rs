@@ -0,0 +1,117 @@
+//! 多核安全的行缓冲输出。
+//!
+//! 每个硬件线程先把输出写进自己的行缓冲，遇到换行或缓冲满时，
+//! 持锁把整行连同前缀一起写到设备，因此不同硬件线程的输出不会在行内交错。
+//!
+//! 前缀形如 `[    1.234567] [hart 0] `，之后是 `rcore_console` 日志格式自带的日志级别。
+//! 写到设备的行连同前缀记入 [`history`](crate::history)。
+
+use crate::{device, history, ConsoleDevice};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+    ptr::addr_of_mut,
+};
+use machine_info::MAX_HARTS;
+use spin::Mutex;
+
+/// 行缓冲容量，超长的行会被分成多行。
+const LINE_CAP: usize = 256;
+
+/// CLINT 中 `mtime` 的偏移。
+const MTIME_OFFSET: usize = 0xbff8;
+
+/// 设备锁，持有时才能向设备写。
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// 每个硬件线程的行缓冲，只由对应的硬件线程访问。
+///
+/// 硬件线程号超出 [`MAX_HARTS`] 的不经缓冲，直接持锁输出。
+static mut LINES: [Line; MAX_HARTS] = [Line::EMPTY; MAX_HARTS];
+
+/// `mtime` 的地址和频率，用于生成时间戳。
+static mut TIMER: (usize, usize) = (0, 0);
+
+struct Line {
+    len: usize,
+    buf: [u8; LINE_CAP],
+}
+
+impl Line {
+    const EMPTY: Self = Self {
+        len: 0,
+        buf: [0; LINE_CAP],
+    };
+}
+
+/// 设置时间戳的来源。没有 CLINT 时不输出时间戳。
+pub(crate) fn set_timer(clint: &Range<usize>, frequency: usize) {
+    if !clint.is_empty() {
+        unsafe { TIMER = (clint.start + MTIME_OFFSET, frequency) };
+    }
+}
+
+/// 写入当前硬件线程的行缓冲。
+pub(crate) fn put_bytes(bytes: &[u8]) {
+    let hartid = hartid();
+    if hartid >= MAX_HARTS {
+        let _guard = LOCK.lock();
+        bytes.iter().for_each(|c| device().put_char(*c));
+        history::push(bytes);
+        return;
+    }
+    let line = unsafe { &mut (*addr_of_mut!(LINES))[hartid] };
+    for &c in bytes {
+        line.buf[line.len] = c;
+        line.len += 1;
+        if c == b'\n' || line.len == LINE_CAP {
+            flush(hartid, &line.buf[..line.len]);
+            line.len = 0;
+        }
+    }
+}
+
+/// 持锁直接输出，不经过行缓冲，也不加前缀。
+///
+/// 用于转发 S 态软件的输出，它们有自己的格式。
+pub fn write_raw(bytes: &[u8]) {
+    let _guard = LOCK.lock();
+    bytes.iter().for_each(|c| device().put_char(*c));
+}
+
+/// 持锁输出一行。
+fn flush(hartid: usize, line: &[u8]) {
+    let _guard = LOCK.lock();
+    let _ = write_prefix(&mut Raw, hartid);
+    line.iter().for_each(|c| device().put_char(*c));
+    history::push(line);
+}
+
+fn write_prefix(w: &mut impl Write, hartid: usize) -> fmt::Result {
+    let (mtime, frequency) = unsafe { TIMER };
+    if mtime != 0 && frequency != 0 {
+        let time = unsafe { (mtime as *const u64).read_volatile() } as usize;
+        let secs = time / frequency;
+        let micros = time % frequency * 1_000_000 / frequency;
+        write!(w, "[{secs:>5}.{micros:06}] ")?;
+    }
+    write!(w, "[hart {hartid}] ")
+}
+
+/// 不加锁地写设备并记入历史，只能在持锁时使用。
+struct Raw;
+
+impl Write for Raw {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|c| device().put_char(c));
+        history::push(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[inline]
+pub(crate) fn hartid() -> usize {
+    let ans: usize;
+    unsafe { core::arch::asm!("csrr {}, mhartid", out(reg) ans) };
+    ans
+}
@@ -0,0 +1,207 @@
+//! 中断驱动的接收缓冲。
+//!
+//! 串口的接收中断经 PLIC 送到一个硬件线程的机器态上下文，
+//! [`handle_interrupt`] 把设备中的数据全部搬进环形缓冲，[`get_char`] 从缓冲取数据。
+//! 这样 S 态软件忙于其他工作时到达的输入也不会丢失。
+//!
+//! 没有 PLIC 或设备不支持接收中断时，[`get_char`] 退化为轮询设备。
+//!
+//! `mip.SEIP` 只在接收中断所在的硬件线程上修改。其他硬件线程读缓冲后只记下新的状态，
+//! 经 [`set_forward`] 给出的函数请求那个硬件线程调用 [`sync_seip`]。
+
+use crate::{device, ConsoleDevice};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use machine_info::MachineInfo;
+use spin::Mutex;
+
+/// 环形缓冲容量，满时丢弃新到达的数据。
+const RING_CAP: usize = 256;
+
+// PLIC 寄存器偏移
+const PRIORITY: usize = 0;
+const ENABLE: usize = 0x2000;
+const ENABLE_STRIDE: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_STRIDE: usize = 0x1000;
+const THRESHOLD: usize = 0;
+const CLAIM: usize = 4;
+
+const MIE_MEIE: usize = 1 << 11;
+const MIP_SEIP: usize = 1 << 9;
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    head: 0,
+    len: 0,
+    buf: [0; RING_CAP],
+});
+
+/// 接收中断是否已开启。
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 缓冲非空时是否置位 `mip.SEIP`。
+static NOTIFY: AtomicBool = AtomicBool::new(false);
+
+/// 缓冲非空、应当置位 `mip.SEIP`。
+static SEIP: AtomicBool = AtomicBool::new(false);
+
+/// PLIC 基地址、串口中断号和接收中断的机器态上下文号。
+static mut ROUTE: (usize, u32, usize) = (0, 0, 0);
+
+/// 接收中断所在的硬件线程。
+static mut HART: usize = 0;
+
+/// 请求接收中断所在的硬件线程同步 `mip.SEIP`。
+static mut FORWARD: Option<fn(usize)> = None;
+
+struct Ring {
+    head: usize,
+    len: usize,
+    buf: [u8; RING_CAP],
+}
+
+impl Ring {
+    #[inline]
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, c: u8) {
+        if self.len < RING_CAP {
+            self.buf[(self.head + self.len) % RING_CAP] = c;
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+        let c = self.buf[self.head];
+        self.head = (self.head + 1) % RING_CAP;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+/// 把串口接收中断送到 `hartid` 的机器态上下文，并打开设备的接收中断和 `mie.MEIE`。
+///
+/// 没有 PLIC、串口没有中断号或设备不支持接收中断时返回 `false`，此后保持轮询。
+/// 必须在控制台初始化后，由 `hartid` 自己调用一次。
+pub fn enable(machine: &MachineInfo, hartid: usize) -> bool {
+    let plic = &machine.plic;
+    let irq = machine.uart.irq;
+    let Some(context) = plic.m_context.get(hartid).copied().flatten() else {
+        return false;
+    };
+    if plic.reg.is_empty() || irq == 0 || !device().set_rx_interrupt(true) {
+        return false;
+    }
+    let base = plic.reg.start;
+    let context = context as usize;
+    unsafe {
+        ROUTE = (base, irq, context);
+        HART = hartid;
+        write(base + PRIORITY + 4 * irq as usize, 1);
+        let enable = base + ENABLE + ENABLE_STRIDE * context + 4 * (irq as usize / 32);
+        write(enable, read(enable) | 1 << (irq % 32));
+        write(base + CONTEXT + CONTEXT_STRIDE * context + THRESHOLD, 0);
+        asm!("csrs mie, {}", in(reg) MIE_MEIE);
+    }
+    ENABLED.store(true, Ordering::Release);
+    true
+}
+
+/// 设置其他硬件线程改变 `mip.SEIP` 状态时的通知函数，参数是接收中断所在的硬件线程。
+///
+/// 通知函数应当让那个硬件线程调用 [`sync_seip`]。没有设置时，其他硬件线程的修改
+/// 等到那个硬件线程下次处理接收中断或读缓冲时才生效。
+pub fn set_forward(forward: fn(usize)) {
+    unsafe { FORWARD = Some(forward) };
+}
+
+/// 按记下的状态设置当前硬件线程的 `mip.SEIP`，只应在接收中断所在的硬件线程上调用。
+pub fn sync_seip() {
+    let pending = SEIP.load(Ordering::Acquire);
+    unsafe {
+        if pending {
+            asm!("csrs mip, {}", in(reg) MIP_SEIP);
+        } else {
+            asm!("csrc mip, {}", in(reg) MIP_SEIP);
+        }
+    }
+}
+
+/// 设置缓冲非空时是否置位 `mip.SEIP` 通知 S 态。
+///
+/// 置位的 `mip.SEIP` 在缓冲被读空时清除。
+pub fn set_notify_supervisor(enable: bool) {
+    let ring = RING.lock();
+    NOTIFY.store(enable, Ordering::Relaxed);
+    set_seip(enable && !ring.is_empty());
+}
+
+/// 处理机器态外部中断：认领 PLIC 中断，搬运串口数据，然后完成中断。
+///
+/// 由 [`enable`] 指定的硬件线程在 `mcause` 为机器态外部中断时调用。
+pub fn handle_interrupt() {
+    let (base, irq, context) = unsafe { ROUTE };
+    let claim = base + CONTEXT + CONTEXT_STRIDE * context + CLAIM;
+    let id = read(claim);
+    if id == 0 {
+        return;
+    }
+    if id == irq {
+        let mut ring = RING.lock();
+        while let Some(c) = device().get_char() {
+            ring.push(c);
+        }
+        update_seip(!ring.is_empty());
+    }
+    write(claim, id);
+}
+
+/// 非阻塞地接收一个字节。
+///
+/// 接收中断开启后从缓冲读取，否则直接轮询设备。
+pub fn get_char() -> Option<u8> {
+    if !ENABLED.load(Ordering::Acquire) {
+        return device().get_char();
+    }
+    let mut ring = RING.lock();
+    let c = ring.pop();
+    update_seip(!ring.is_empty());
+    c
+}
+
+#[inline]
+fn update_seip(pending: bool) {
+    if NOTIFY.load(Ordering::Relaxed) {
+        set_seip(pending);
+    }
+}
+
+/// 记下 `mip.SEIP` 的新状态，在接收中断所在的硬件线程上生效。
+fn set_seip(pending: bool) {
+    SEIP.store(pending, Ordering::Release);
+    let hart = unsafe { HART };
+    let this: usize;
+    unsafe { asm!("csrr {}, mhartid", out(reg) this) };
+    if this == hart {
+        sync_seip();
+    } else if let Some(forward) = unsafe { FORWARD } {
+        forward(hart);
+    }
+}
+
+#[inline]
+fn read(addr: usize) -> u32 {
+    unsafe { (addr as *const u32).read_volatile() }
+}
+
+#[inline]
+fn write(addr: usize, val: u32) {
+    unsafe { (addr as *mut u32).write_volatile(val) }
+}
@@ -0,0 +1,75 @@
+//! SBI §4 Base 扩展。
+//!
+//! `probe_extension` 查询扩展注册表，只报告已注册的扩展。
+
+use crate::{probe, Extension, TrapContext};
+use core::arch::asm;
+use sbi_spec::{base::*, binary::SbiRet};
+
+/// 实现的 SBI 规范版本 2.0，主版本号在 24 位以上。
+///
+/// DBCN 等扩展是 2.0 版本加入的。
+const SPEC_VERSION: usize = 2 << 24;
+
+/// 实现编号。
+///
+/// 在依赖 RustSBI 库之前我们还不能自称为 RustSBI，这里用 TinySBI 缩写 `TSB` 的 ASCII，
+/// 不与规范登记的任何实现冲突，并且不超过 24 位，可以用来构造固件扩展号。
+pub(crate) const IMPL_ID: usize = 0x54_5342;
+
+/// Base 扩展。
+pub struct Base {
+    impl_version: usize,
+}
+
+impl Base {
+    /// 构造 Base 扩展。
+    ///
+    /// `version` 是形如 `major.minor.patch` 的实现版本，通常传入 `env!("CARGO_PKG_VERSION")`，
+    /// 编码为 `major << 16 | minor << 8 | patch`。
+    /// 有数字和 `.` 以外的字符时 panic，在常量中构造时编译失败。
+    pub const fn new(version: &str) -> Self {
+        let version = version.as_bytes();
+        let mut impl_version = 0;
+        let mut part = 0;
+        let mut i = 0;
+        while i < version.len() {
+            match version[i] {
+                b'.' => {
+                    impl_version = (impl_version | part) << 8;
+                    part = 0;
+                }
+                c @ b'0'..=b'9' => part = part * 10 + (c - b'0') as usize,
+                _ => panic!("invalid implementation version"),
+            }
+            i += 1;
+        }
+        Self {
+            impl_version: impl_version | part,
+        }
+    }
+}
+
+/// 读只读的机器态信息寄存器，未实现时读出 0。
+macro_rules! csr {
+    ($name:literal) => {{
+        let value: usize;
+        unsafe { asm!(concat!("csrr {}, ", $name), out(reg) value) };
+        value
+    }};
+}
+
+impl Extension for Base {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            GET_SBI_SPEC_VERSION => SbiRet::success(SPEC_VERSION),
+            GET_SBI_IMPL_ID => SbiRet::success(IMPL_ID),
+            GET_SBI_IMPL_VERSION => SbiRet::success(self.impl_version),
+            PROBE_EXTENSION => SbiRet::success(probe(ctx.a(0))),
+            GET_MVENDORID => SbiRet::success(csr!("mvendorid")),
+            GET_MARCHID => SbiRet::success(csr!("marchid")),
+            GET_MIMPID => SbiRet::success(csr!("mimpid")),
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! 引导硬件线程的公共初始化。
+
+use crate::{ipi, memory, register_interrupt, MACHINE_EXTERNAL};
+use core::ops::Range;
+use machine_info::MachineInfo;
+use rcore_console::{log, print, println};
+
+/// 由引导硬件线程完成的公共初始化：收集机器信息，初始化控制台和输入中断，打印启动信息。
+///
+/// `kernel` 是内核镜像的范围，设备树与它或固件自身重叠时会被搬走。
+/// 没有设备树时为内核生成一个，保留表里只列出固件自身。
+///
+/// 只能在启动时由一个硬件线程调用。
+pub fn boot(hartid: usize, dtb_ptr: usize, kernel: Range<usize>) -> MachineInfo {
+    let reserved = [linker::image(), kernel];
+    let mut machine = MachineInfo::detect(dtb_ptr, &reserved).unwrap();
+    if machine.dtb.is_empty() {
+        machine.generate_dtb(hartid, &reserved[..1]).unwrap();
+    }
+    memory::init(&machine.mem);
+    console::init(&machine);
+    rcore_console::set_log_level(option_env!("LOG"));
+    register_interrupt(MACHINE_EXTERNAL, console::rx::handle_interrupt);
+    // 输入由接收中断缓冲，没有 PLIC 时保持轮询
+    let rx_irq = console::rx::enable(&machine, hartid);
+    // 其他硬件线程读缓冲后，请求接收中断所在的硬件线程更新 mip.SEIP
+    console::rx::set_forward(|hart| {
+        if ipi::exists() {
+            ipi::request(hart, ipi::SEIP);
+        }
+    });
+    println!(
+        r"
+___       __ __ _
+ | . _   (_ |__)|
+ | || |\/__)|__)|
+-------/---------
+boot hart: {hartid}
+uart rx  : {}
+{machine}",
+        if rx_irq { "interrupt" } else { "polling" }
+    );
+    log::debug!("{machine:?}");
+    print!("{}", machine.dump());
+    machine
+}
@@ -0,0 +1,49 @@
+//! CLINT 的软件中断和定时器。
+
+use core::ops::Range;
+
+const MSIP: usize = 0;
+const MTIMECMP: usize = 0x4000;
+const MTIME: usize = 0xbff8;
+
+/// CLINT 基地址，没有 CLINT 时为 0。
+static mut BASE: usize = 0;
+
+/// 记录 CLINT 地址。
+pub fn init(clint: &Range<usize>) {
+    unsafe { BASE = clint.start };
+}
+
+/// 是否有 CLINT。
+#[inline]
+pub fn exists() -> bool {
+    unsafe { BASE != 0 }
+}
+
+/// 设置 `hartid` 的 `mtimecmp`。
+#[inline]
+pub fn set_timecmp(hartid: usize, time: u64) {
+    unsafe { ((BASE + MTIMECMP + 8 * hartid) as *mut u64).write_volatile(time) }
+}
+
+/// 读取 `mtime`，没有 CLINT 时为 0。
+#[inline]
+pub fn mtime() -> u64 {
+    if exists() {
+        unsafe { ((BASE + MTIME) as *const u64).read_volatile() }
+    } else {
+        0
+    }
+}
+
+/// 置位 `hartid` 的 `msip`，向它发送机器态软件中断。
+#[inline]
+pub fn set_msip(hartid: usize) {
+    unsafe { ((BASE + MSIP + 4 * hartid) as *mut u32).write_volatile(1) }
+}
+
+/// 清除 `hartid` 的 `msip`。
+#[inline]
+pub fn clear_msip(hartid: usize) {
+    unsafe { ((BASE + MSIP + 4 * hartid) as *mut u32).write_volatile(0) }
+}
@@ -0,0 +1,150 @@
+//! SBI §14 CPPC 扩展。
+//!
+//! 没有真正的调频硬件，每个硬件线程有一组模拟的 CPPC 寄存器：
+//!
+//! - 性能等级是固定的常数，最高、标称和参考性能都是 [`NOMINAL`]；
+//! - 期望、最低和最高性能以及使能寄存器可以写入，只记录不生效；
+//! - 参考性能计数器是 `mtime`，交付性能计数器是 `mcycle`。
+//!
+//! 保留的寄存器号返回 `INVALID_PARAM`，没有实现的寄存器探测结果为 0，读写返回 `NOT_SUPPORTED`。
+
+use crate::{clint, denied, hartid, register, Extension, TrapContext};
+use core::arch::asm;
+use machine_info::{MachineInfo, MAX_HARTS};
+use sbi_spec::binary::SbiRet;
+
+/// CPPC 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_CPPC: usize = 0x43505043;
+const PROBE: usize = 0;
+const READ: usize = 1;
+const READ_HI: usize = 2;
+const WRITE: usize = 3;
+
+// 寄存器号，见 SBI §14 的寄存器表。
+const HIGHEST_PERFORMANCE: u32 = 0x00;
+const NOMINAL_PERFORMANCE: u32 = 0x01;
+const LOWEST_NONLINEAR_PERFORMANCE: u32 = 0x02;
+const LOWEST_PERFORMANCE: u32 = 0x03;
+const DESIRED_PERFORMANCE: u32 = 0x05;
+const MINIMUM_PERFORMANCE: u32 = 0x06;
+const MAXIMUM_PERFORMANCE: u32 = 0x07;
+const COUNTER_WRAPAROUND_TIME: u32 = 0x0a;
+const REFERENCE_PERFORMANCE_COUNTER: u32 = 0x0b;
+const DELIVERED_PERFORMANCE_COUNTER: u32 = 0x0c;
+const CPPC_ENABLE: u32 = 0x0e;
+const REFERENCE_PERFORMANCE: u32 = 0x12;
+/// 最后一个非保留的常规寄存器号。
+const LAST_REGISTER: u32 = 0x14;
+const TRANSITION_LATENCY: u32 = 0x8000_0000;
+
+/// 标称性能，也是最高性能和参考性能。
+const NOMINAL: u32 = 100;
+/// 最低性能，也是最低的非线性性能。
+const LOWEST: u32 = 10;
+
+/// 可写的寄存器。
+#[derive(Clone, Copy)]
+struct Registers {
+    desired: u32,
+    minimum: u32,
+    maximum: u32,
+    enable: u32,
+}
+
+static mut REGISTERS: [Registers; MAX_HARTS] = [Registers {
+    desired: NOMINAL,
+    minimum: LOWEST,
+    maximum: NOMINAL,
+    enable: 0,
+}; MAX_HARTS];
+
+/// CPPC 扩展。
+pub struct Cppc;
+
+impl Cppc {
+    /// 注册 CPPC 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        clint::init(&machine.clint);
+        register(EID_CPPC, self);
+    }
+}
+
+impl Extension for Cppc {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let reg_id = ctx.a(0) as u32;
+        let width = match width(reg_id) {
+            Ok(width) => width,
+            Err(err) => return err,
+        };
+        match fid {
+            PROBE => SbiRet::success(width),
+            _ if width == 0 => SbiRet::not_supported(),
+            READ => SbiRet::success(read(reg_id) as _),
+            // 64 位机器上一次就能读出完整的值
+            READ_HI => SbiRet::success(0),
+            WRITE => write(reg_id, ctx.a(1)),
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
+
+/// 寄存器的位宽，没有实现时为 0，保留的寄存器号返回 `INVALID_PARAM`。
+fn width(reg_id: u32) -> Result<usize, SbiRet> {
+    match reg_id {
+        HIGHEST_PERFORMANCE
+        | NOMINAL_PERFORMANCE
+        | LOWEST_NONLINEAR_PERFORMANCE
+        | LOWEST_PERFORMANCE
+        | DESIRED_PERFORMANCE
+        | MINIMUM_PERFORMANCE
+        | MAXIMUM_PERFORMANCE
+        | CPPC_ENABLE
+        | REFERENCE_PERFORMANCE
+        | TRANSITION_LATENCY => Ok(32),
+        COUNTER_WRAPAROUND_TIME | REFERENCE_PERFORMANCE_COUNTER | DELIVERED_PERFORMANCE_COUNTER => {
+            Ok(64)
+        }
+        0..=LAST_REGISTER => Ok(0),
+        _ => Err(SbiRet::invalid_param()),
+    }
+}
+
+/// 读已实现的寄存器。
+fn read(reg_id: u32) -> u64 {
+    let regs = unsafe { &REGISTERS[hartid()] };
+    match reg_id {
+        HIGHEST_PERFORMANCE | NOMINAL_PERFORMANCE | REFERENCE_PERFORMANCE => NOMINAL as _,
+        LOWEST_NONLINEAR_PERFORMANCE | LOWEST_PERFORMANCE => LOWEST as _,
+        DESIRED_PERFORMANCE => regs.desired as _,
+        MINIMUM_PERFORMANCE => regs.minimum as _,
+        MAXIMUM_PERFORMANCE => regs.maximum as _,
+        CPPC_ENABLE => regs.enable as _,
+        // 64 位计数器认为不会回绕
+        COUNTER_WRAPAROUND_TIME => 0,
+        REFERENCE_PERFORMANCE_COUNTER => clint::mtime(),
+        DELIVERED_PERFORMANCE_COUNTER => {
+            let cycle: u64;
+            unsafe { asm!("csrr {}, mcycle", out(reg) cycle) };
+            cycle
+        }
+        // 性能等级的切换立即完成
+        TRANSITION_LATENCY => 0,
+        _ => unreachable!(),
+    }
+}
+
+/// 写已实现的寄存器，只读寄存器返回 `DENIED`。
+fn write(reg_id: u32, value: usize) -> SbiRet {
+    let regs = unsafe { &mut REGISTERS[hartid()] };
+    let value = value as u32;
+    match reg_id {
+        DESIRED_PERFORMANCE => regs.desired = value,
+        MINIMUM_PERFORMANCE => regs.minimum = value,
+        MAXIMUM_PERFORMANCE => regs.maximum = value,
+        CPPC_ENABLE => regs.enable = value & 1,
+        _ => return denied(),
+    }
+    SbiRet::success(0)
+}
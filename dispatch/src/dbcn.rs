@@ -0,0 +1,82 @@
+//! SBI §12 DBCN 扩展。
+//!
+//! 缓冲区以物理地址给出，分为低 XLEN 位和高 XLEN 位，64 位机器上高位必须为 0。
+//! 缓冲区必须完全位于内存中，否则返回 `INVALID_PARAM`。
+//!
+//! 一次写入至多 [`MAX_TRANSFER`] 字节，以免长时间占用控制台锁；读取只取出已经收到的字节。
+//! 返回值是实际传输的字节数，调用方自行处理剩余部分。
+
+use crate::{memory, register, Extension, TrapContext};
+use console::Device;
+use core::ops::Range;
+use sbi_spec::binary::SbiRet;
+
+/// DBCN 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_DBCN: usize = 0x4442434e;
+const CONSOLE_WRITE: usize = 0;
+const CONSOLE_READ: usize = 1;
+const CONSOLE_WRITE_BYTE: usize = 2;
+
+/// 一次写入的字节数上限。
+const MAX_TRANSFER: usize = 256;
+
+/// DBCN 扩展。
+pub struct Dbcn;
+
+impl Dbcn {
+    /// 注册 DBCN 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self) {
+        register(EID_DBCN, self);
+    }
+}
+
+impl Extension for Dbcn {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        if matches!(console::device(), Device::None) {
+            return SbiRet::failed();
+        }
+        let memory = &memory::range();
+        match fid {
+            CONSOLE_WRITE => match buffer(memory, ctx.a(0).min(MAX_TRANSFER), ctx.a(1), ctx.a(2)) {
+                Some(buf) => {
+                    console::write_raw(buf);
+                    SbiRet::success(buf.len())
+                }
+                None => SbiRet::invalid_param(),
+            },
+            CONSOLE_READ => match buffer(memory, ctx.a(0), ctx.a(1), ctx.a(2)) {
+                Some(buf) => {
+                    let n = buf
+                        .iter_mut()
+                        .map_while(|b| console::rx::get_char().map(|c| *b = c))
+                        .count();
+                    SbiRet::success(n)
+                }
+                None => SbiRet::invalid_param(),
+            },
+            CONSOLE_WRITE_BYTE => {
+                console::write_raw(&[ctx.a(0) as u8]);
+                SbiRet::success(0)
+            }
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
+
+/// 检查缓冲区是否完全位于内存 `memory` 中。
+///
+/// 缓冲区属于 S 态软件，只在这次调用期间使用。
+pub(crate) fn buffer(
+    memory: &Range<usize>,
+    num_bytes: usize,
+    base_lo: usize,
+    base_hi: usize,
+) -> Option<&'static mut [u8]> {
+    let end = base_lo.checked_add(num_bytes)?;
+    if base_hi != 0 || base_lo < memory.start || end > memory.end {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts_mut(base_lo as _, num_bytes) })
+}
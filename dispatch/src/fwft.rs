@@ -0,0 +1,149 @@
+//! SBI §18 FWFT 扩展。
+//!
+//! 每个特性对应 `medeleg` 或 `menvcfg` 中的几位，读写的是当前硬件线程的 CSR。
+//! 依赖 ISA 扩展的特性，只有在所有硬件线程都支持这个扩展时才可用，见 [`IsaExtensions`]。
+//!
+//! 设置时带上锁定标志后，这个硬件线程上的这个特性不能再修改，直到硬件线程重新初始化。
+
+use crate::{denied, hartid, register, Extension, TrapContext};
+use core::arch::asm;
+use machine_info::{IsaExtensions, MachineInfo, MAX_HARTS};
+use sbi_spec::binary::SbiRet;
+
+/// FWFT 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_FWFT: usize = 0x46574654;
+const SET: usize = 0;
+const GET: usize = 1;
+
+// 特性号。
+const MISALIGNED_EXC_DELEG: u32 = 0;
+const LANDING_PAD: u32 = 1;
+const SHADOW_STACK: u32 = 2;
+const DOUBLE_TRAP: u32 = 3;
+const PTE_AD_HW_UPDATING: u32 = 4;
+/// 支持的特性数，特性号依次排列。
+const FEATURES: usize = 5;
+
+/// 设置后锁定。
+const FLAG_LOCK: usize = 1 << 0;
+
+/// 特性已锁定，SBI 3.0 新增的错误码。
+const RET_ERR_DENIED_LOCKED: usize = -14isize as _;
+
+/// 读不对齐和写不对齐异常。
+const MEDELEG_MISALIGNED: usize = 1 << 4 | 1 << 6;
+const MENVCFG_LPE: usize = 1 << 2;
+const MENVCFG_SSE: usize = 1 << 3;
+const MENVCFG_DTE: usize = 1 << 59;
+const MENVCFG_ADUE: usize = 1 << 61;
+
+/// 所有硬件线程都支持的 ISA 扩展。
+static mut ISA: IsaExtensions = IsaExtensions::NONE;
+/// 每个硬件线程已锁定的特性。
+static mut LOCKED: [u32; MAX_HARTS] = [0; MAX_HARTS];
+
+/// 特性控制的 CSR 位。
+enum Control {
+    Medeleg(usize),
+    Menvcfg(usize),
+}
+
+/// FWFT 扩展。
+pub struct Fwft;
+
+impl Fwft {
+    /// 注册 FWFT 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        unsafe { ISA = machine.isa };
+        register(EID_FWFT, self);
+    }
+}
+
+impl Extension for Fwft {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let feature = ctx.a(0) as u32;
+        let control = match control(feature) {
+            Ok(control) => control,
+            Err(err) => return err,
+        };
+        match fid {
+            SET => set(feature, control, ctx.a(1), ctx.a(2)),
+            GET => SbiRet::success(control.get() as _),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, hartid: usize) {
+        unsafe { LOCKED[hartid] = 0 };
+    }
+}
+
+/// 特性对应的 CSR 位。
+///
+/// 未定义的特性返回 `DENIED`，定义了但依赖的 ISA 扩展不存在时返回 `NOT_SUPPORTED`。
+fn control(feature: u32) -> Result<Control, SbiRet> {
+    let (control, isa) = match feature {
+        MISALIGNED_EXC_DELEG => (Control::Medeleg(MEDELEG_MISALIGNED), IsaExtensions::NONE),
+        LANDING_PAD => (Control::Menvcfg(MENVCFG_LPE), IsaExtensions::ZICFILP),
+        SHADOW_STACK => (Control::Menvcfg(MENVCFG_SSE), IsaExtensions::ZICFISS),
+        DOUBLE_TRAP => (Control::Menvcfg(MENVCFG_DTE), IsaExtensions::SSDBLTRP),
+        PTE_AD_HW_UPDATING => (Control::Menvcfg(MENVCFG_ADUE), IsaExtensions::SVADU),
+        _ => return Err(denied()),
+    };
+    if unsafe { ISA }.contains(isa) {
+        Ok(control)
+    } else {
+        Err(SbiRet::not_supported())
+    }
+}
+
+fn set(feature: u32, control: Control, value: usize, flags: usize) -> SbiRet {
+    if flags & !FLAG_LOCK != 0 || value > 1 {
+        return SbiRet::invalid_param();
+    }
+    debug_assert!((feature as usize) < FEATURES);
+    let locked = unsafe { &mut LOCKED[hartid()] };
+    if *locked >> feature & 1 == 1 {
+        return SbiRet {
+            error: RET_ERR_DENIED_LOCKED,
+            value: 0,
+        };
+    }
+    control.set(value == 1);
+    if flags & FLAG_LOCK != 0 {
+        *locked |= 1 << feature;
+    }
+    SbiRet::success(0)
+}
+
+impl Control {
+    fn get(&self) -> bool {
+        let (csr, bits) = match *self {
+            Self::Medeleg(bits) => {
+                let medeleg: usize;
+                unsafe { asm!("csrr {}, medeleg", out(reg) medeleg) };
+                (medeleg, bits)
+            }
+            Self::Menvcfg(bits) => {
+                let menvcfg: usize;
+                unsafe { asm!("csrr {}, 0x30a", out(reg) menvcfg) };
+                (menvcfg, bits)
+            }
+        };
+        csr & bits == bits
+    }
+
+    fn set(&self, enable: bool) {
+        unsafe {
+            match (self, enable) {
+                (Self::Medeleg(bits), true) => asm!("csrs medeleg, {}", in(reg) *bits),
+                (Self::Medeleg(bits), false) => asm!("csrc medeleg, {}", in(reg) *bits),
+                // menvcfg
+                (Self::Menvcfg(bits), true) => asm!("csrs 0x30a, {}", in(reg) *bits),
+                (Self::Menvcfg(bits), false) => asm!("csrc 0x30a, {}", in(reg) *bits),
+            }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! SBI §9 HSM 扩展。
+//!
+//! 每个硬件线程的状态是一个原子变量，状态转换用比较交换完成，失败说明当前状态不允许这个转换。
+//!
+//! 停止的硬件线程停在机器态，反复 `wfi` 等待机器态软件中断。
+//! `hart_start` 把目标的状态改为 `START_PENDING`，把入口和参数放进目标的信箱，再通过 `msip` 唤醒它。
+//!
+//! 非保持挂起把恢复入口放进自己的信箱，`wfi` 醒来后从热启动入口重新初始化机器态，
+//! 再以 S 态进入恢复入口，挂起前的 S 态状态不再保留。
+
+use crate::{
+    clint, enter_supervisor, hartid, ipi, memory, register, trap::restore_scratch, Extension,
+    TrapContext,
+};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use machine_info::{MachineInfo, MAX_HARTS};
+use sbi_spec::{binary::SbiRet, hsm::*};
+use spin::Mutex;
+
+/// 每个硬件线程的状态。
+static STATE: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const STOPPED: AtomicUsize = AtomicUsize::new(HART_STATE_STOPPED);
+    [STOPPED; MAX_HARTS]
+};
+
+/// 每个硬件线程的启动信箱，保存 S 态入口和 `opaque`，非保持挂起时保存恢复入口。
+static START: [Mutex<Option<(usize, usize)>>; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Mutex<Option<(usize, usize)>> = Mutex::new(None);
+    [EMPTY; MAX_HARTS]
+};
+
+/// 可用的硬件线程数。
+static mut SMP: usize = 1;
+
+/// HSM 扩展。
+pub struct Hsm {
+    warm_start: unsafe extern "C" fn() -> !,
+}
+
+impl Hsm {
+    /// 创建 HSM 扩展，非保持挂起后从 `warm_start` 恢复，见 `linker::warm0!`。
+    #[inline]
+    pub const fn new(warm_start: unsafe extern "C" fn() -> !) -> Self {
+        Self { warm_start }
+    }
+
+    /// 注册 HSM 扩展。引导硬件线程 `hartid` 处于 `STARTED`，其他硬件线程处于 `STOPPED`。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo, hartid: usize) {
+        ipi::init(machine);
+        unsafe { SMP = machine.smp.min(MAX_HARTS) };
+        STATE[hartid].store(HART_STATE_STARTED, Ordering::Release);
+        register(EID_HSM, self);
+    }
+
+    /// 停止当前硬件线程，等待 `hart_start` 以 S 态进入指定的入口。
+    ///
+    /// 用于启动时的非引导硬件线程，必须在 [`init`](crate::init) 之后调用。
+    pub fn park(&self) -> ! {
+        park(hartid())
+    }
+
+    /// 从非保持挂起恢复，以 S 态进入挂起时给出的恢复入口。
+    ///
+    /// 由热启动入口在 [`init`](crate::init) 之后调用。
+    pub fn resume(&self) -> ! {
+        let hartid = hartid();
+        let (resume_addr, opaque) = START[hartid].lock().take().unwrap();
+        STATE[hartid].store(HART_STATE_RESUME_PENDING, Ordering::Release);
+        STATE[hartid].store(HART_STATE_STARTED, Ordering::Release);
+        unsafe { enter_supervisor(hartid, opaque, resume_addr) }
+    }
+}
+
+impl Extension for Hsm {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            HART_START => hart_start(ctx.a(0), ctx.a(1), ctx.a(2)),
+            HART_STOP => hart_stop(ctx),
+            HART_GET_STATUS => hart_get_status(ctx.a(0)),
+            HART_SUSPEND => self.hart_suspend(ctx),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        // 停止的硬件线程靠机器态软件中断唤醒
+        ipi::init_hart();
+    }
+}
+
+fn hart_start(hartid: usize, start_addr: usize, opaque: usize) -> SbiRet {
+    if hartid >= unsafe { SMP } {
+        return SbiRet::invalid_param();
+    }
+    if !memory::range().contains(&start_addr) {
+        return SbiRet::invalid_address();
+    }
+    if STATE[hartid]
+        .compare_exchange(
+            HART_STATE_STOPPED,
+            HART_STATE_START_PENDING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        return SbiRet::already_available();
+    }
+    *START[hartid].lock() = Some((start_addr, opaque));
+    if clint::exists() {
+        clint::set_msip(hartid);
+    }
+    SbiRet::success(0)
+}
+
+fn hart_stop(ctx: &TrapContext) -> SbiRet {
+    let hartid = hartid();
+    if STATE[hartid]
+        .compare_exchange(
+            HART_STATE_STARTED,
+            HART_STATE_STOP_PENDING,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        )
+        .is_err()
+    {
+        return SbiRet::failed();
+    }
+    // 不再从这次陷入返回，启动时直接从 park 进入 S 态
+    restore_scratch(ctx);
+    STATE[hartid].store(HART_STATE_STOPPED, Ordering::Release);
+    park(hartid)
+}
+
+fn hart_get_status(hartid: usize) -> SbiRet {
+    if hartid >= unsafe { SMP } {
+        return SbiRet::invalid_param();
+    }
+    SbiRet::success(STATE[hartid].load(Ordering::Acquire))
+}
+
+impl Hsm {
+    fn hart_suspend(&self, ctx: &TrapContext) -> SbiRet {
+        let (suspend_type, resume_addr, opaque) = (ctx.a(0) as u32, ctx.a(1), ctx.a(2));
+        match suspend_type {
+            HART_SUSPEND_TYPE_RETENTIVE => {}
+            HART_SUSPEND_TYPE_NON_RETENTIVE => return self.suspend(ctx, resume_addr, opaque),
+            // 平台自定义的挂起类型，本固件没有定义
+            0x1000_0000..=0x7fff_ffff | 0x9000_0000..=0xffff_ffff => {
+                return SbiRet::not_supported()
+            }
+            // 保留的挂起类型
+            _ => return SbiRet::invalid_param(),
+        }
+        let state = &STATE[hartid()];
+        state.store(HART_STATE_SUSPENDED, Ordering::Release);
+        // 任何使能的中断都会唤醒，机器态中断在返回 S 态后处理
+        unsafe { asm!("wfi") };
+        state.store(HART_STATE_RESUME_PENDING, Ordering::Release);
+        state.store(HART_STATE_STARTED, Ordering::Release);
+        SbiRet::success(0)
+    }
+
+    /// 非保持挂起当前硬件线程，醒来后经热启动入口以 S 态进入 `resume_addr`。
+    ///
+    /// 只在 `resume_addr` 无效时返回。
+    pub(crate) fn suspend(&self, ctx: &TrapContext, resume_addr: usize, opaque: usize) -> SbiRet {
+        if !memory::range().contains(&resume_addr) {
+            return SbiRet::invalid_address();
+        }
+        let hartid = hartid();
+        *START[hartid].lock() = Some((resume_addr, opaque));
+        STATE[hartid].store(HART_STATE_SUSPENDED, Ordering::Release);
+        unsafe { asm!("wfi") };
+        // 不再从这次陷入返回
+        restore_scratch(ctx);
+        unsafe { (self.warm_start)() }
+    }
+}
+
+/// 除当前硬件线程外，是否所有硬件线程都处于 `STOPPED`。
+pub(crate) fn others_stopped() -> bool {
+    let this = hartid();
+    (0..unsafe { SMP })
+        .filter(|hart| *hart != this)
+        .all(|hart| STATE[hart].load(Ordering::Acquire) == HART_STATE_STOPPED)
+}
+
+/// 在机器态等待启动请求，然后以 S 态进入请求的入口。
+///
+/// 调用时硬件线程已处于 `STOPPED` 或 `START_PENDING`。
+fn park(hartid: usize) -> ! {
+    let (start_addr, opaque) = loop {
+        // 先清除 msip 再检查，之后到达的请求会让 wfi 立即返回
+        if clint::exists() {
+            clint::clear_msip(hartid);
+        }
+        // 停止的硬件线程也可能收到其他请求，对方在等待它处理
+        ipi::poll();
+        if let Some(start) = START[hartid].lock().take() {
+            break start;
+        }
+        if clint::exists() {
+            unsafe { asm!("wfi") };
+        } else {
+            spin_loop();
+        }
+    };
+    STATE[hartid].store(HART_STATE_STARTED, Ordering::Release);
+    unsafe { enter_supervisor(hartid, opaque, start_addr) }
+}
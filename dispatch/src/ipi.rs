@@ -0,0 +1,177 @@
+//! 硬件线程间的请求。
+//!
+//! 每个硬件线程有一组待处理位。发送方置位后通过 CLINT 的 `msip` 发出机器态软件中断，
+//! 目标在中断中清除 `msip`，处理所有待处理的请求。
+//!
+//! 有 ACLINT SSWI 时，S 态软件中断直接写 SSWI 产生，不经过机器态。
+
+use crate::{clint, hartid, pmu, register_interrupt};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use machine_info::{MachineInfo, MAX_HARTS};
+use sbi_spec::binary::SbiRet;
+
+/// 转发给 S 态的软件中断。
+pub(crate) const SSIP: usize = 1 << 0;
+/// 刷新指令缓存。
+pub(crate) const FENCE_I: usize = 1 << 1;
+/// 刷新地址转换缓存。
+pub(crate) const SFENCE_VMA: usize = 1 << 2;
+/// 处理信箱中的远程栅栏，见 `rfence` 模块。
+pub(crate) const RFENCE: usize = 1 << 3;
+/// 停在机器态，不再返回，用于系统复位之前。
+pub(crate) const HALT: usize = 1 << 4;
+/// 同步串口接收缓冲的 `mip.SEIP`，见 `console::rx`。
+pub(crate) const SEIP: usize = 1 << 5;
+
+const MIP_SSIP: usize = 1 << 1;
+const MIE_MSIE: usize = 1 << 3;
+
+/// 每个硬件线程待处理的请求。
+static PENDING: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NONE: AtomicUsize = AtomicUsize::new(0);
+    [NONE; MAX_HARTS]
+};
+
+/// 已进入固件、能处理请求的硬件线程掩码。
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// ACLINT SSWI 基地址，没有时为 0。
+static mut SSWI: usize = 0;
+
+/// 记录中断设备，注册机器态软件中断。
+pub(crate) fn init(machine: &MachineInfo) {
+    clint::init(&machine.clint);
+    unsafe { SSWI = machine.sswi.start };
+    register_interrupt(crate::MACHINE_SOFT, on_soft);
+}
+
+/// 打开当前硬件线程的机器态软件中断，把它加入可用的硬件线程。
+///
+/// 停在引导代码里的硬件线程不会调用这个函数，不会收到请求。
+pub(crate) fn init_hart() {
+    unsafe { asm!("csrs mie, {}", in(reg) MIE_MSIE) };
+    ONLINE.fetch_or(1 << hartid(), Ordering::Release);
+}
+
+/// 所有可用硬件线程的掩码。
+#[inline]
+pub(crate) fn available() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// 把 `hart_mask` 和 `hart_mask_base` 转换为硬件线程掩码。
+///
+/// `hart_mask_base` 为 -1 表示所有可用的硬件线程。
+/// 掩码中有不可用的硬件线程时返回 `INVALID_PARAM`。
+pub(crate) fn decode_mask(hart_mask: usize, hart_mask_base: usize) -> Result<usize, SbiRet> {
+    if hart_mask_base == usize::MAX {
+        return Ok(available());
+    }
+    if hart_mask == 0 {
+        return Ok(0);
+    }
+    let highest = (usize::BITS - 1 - hart_mask.leading_zeros()) as usize;
+    match hart_mask_base.checked_add(highest) {
+        Some(last) if last < MAX_HARTS && (hart_mask << hart_mask_base) & !available() == 0 => {
+            Ok(hart_mask << hart_mask_base)
+        }
+        _ => Err(SbiRet::invalid_param()),
+    }
+}
+
+/// 是否能发出请求。
+#[inline]
+pub(crate) fn exists() -> bool {
+    clint::exists()
+}
+
+/// 向 `hart` 发送 S 态软件中断。
+pub(crate) fn send_supervisor(hart: usize) {
+    pmu::record(pmu::IPI_SENT);
+    let sswi = unsafe { SSWI };
+    if sswi != 0 {
+        unsafe { ((sswi + 4 * hart) as *mut u32).write_volatile(1) };
+    } else if hart == hartid() {
+        perform(SSIP);
+    } else {
+        request(hart, SSIP);
+    }
+}
+
+/// 向其他硬件线程发出请求。
+pub(crate) fn request(hart: usize, what: usize) {
+    PENDING[hart].fetch_or(what, Ordering::Release);
+    clint::set_msip(hart);
+}
+
+/// 等待 `hart` 处理完 `what` 请求。
+///
+/// 等待期间处理发给自己的请求，因为对方可能也在等待这个硬件线程。
+pub(crate) fn wait(hart: usize, what: usize) {
+    while PENDING[hart].load(Ordering::Acquire) & what != 0 {
+        poll();
+        spin_loop();
+    }
+}
+
+/// 在当前硬件线程上执行请求。
+pub(crate) fn perform(what: usize) {
+    unsafe {
+        match what {
+            SSIP => {
+                pmu::record(pmu::IPI_RECEIVED);
+                asm!("csrs mip, {}", in(reg) MIP_SSIP)
+            }
+            FENCE_I => {
+                pmu::record(pmu::FENCE_I_RECEIVED);
+                asm!("fence.i")
+            }
+            SFENCE_VMA => {
+                pmu::record(pmu::SFENCE_VMA_RECEIVED);
+                asm!("sfence.vma")
+            }
+            RFENCE => crate::rfence::serve(),
+            SEIP => console::rx::sync_seip(),
+            HALT => loop {
+                asm!("wfi");
+            },
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// 处理当前硬件线程的待处理请求，用于在机器态等待其他硬件线程时避免死锁。
+#[inline]
+pub(crate) fn poll() {
+    process(hartid());
+}
+
+/// 机器态软件中断：清除 `msip`，处理待处理的请求。
+fn on_soft() {
+    let hartid = hartid();
+    clint::clear_msip(hartid);
+    process(hartid);
+}
+
+/// 处理 `hartid` 的待处理请求。
+///
+/// 每个请求执行完才清除对应的位，发送方的 [`wait`] 返回时请求已经生效。
+fn process(hartid: usize) {
+    let pending = PENDING[hartid].load(Ordering::Acquire);
+    for what in [SSIP, FENCE_I, SFENCE_VMA, RFENCE, SEIP] {
+        if pending & what != 0 {
+            perform(what);
+            PENDING[hartid].fetch_and(!what, Ordering::Release);
+        }
+    }
+    // HALT 不返回，进入之前清除
+    if pending & HALT != 0 {
+        PENDING[hartid].fetch_and(!HALT, Ordering::Release);
+        perform(HALT);
+    }
+}
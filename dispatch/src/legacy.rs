@@ -0,0 +1,112 @@
+//! SBI §5 Legacy 扩展。
+//!
+//! 旧式调用只在 `a0` 返回一个值，其他寄存器保持不变：
+//! 成功为 0，`console_getchar` 返回读到的字节，没有输入时返回 -1；
+//! 失败时返回实现定义的错误码，这里沿用 `sbi-spec` 的标准错误码。
+//!
+//! 远程操作通过机器态软件中断通知目标硬件线程，见 `ipi` 模块。
+//! 远程栅栏一律全部刷新，并等待所有目标完成后才返回。
+
+use crate::{hartid, ipi, pmu, read_supervisor, register, time, Extension, TrapContext};
+use core::arch::asm;
+use machine_info::MachineInfo;
+use sbi_spec::{binary::SbiRet, legacy::*};
+
+const MIP_SSIP: usize = 1 << 1;
+
+/// 旧式调用。
+pub struct Legacy {
+    shutdown: fn() -> !,
+}
+
+impl Legacy {
+    /// 构造旧式调用，`shutdown` 实现关机。
+    #[inline]
+    pub const fn new(shutdown: fn() -> !) -> Self {
+        Self { shutdown }
+    }
+
+    /// 注册所有旧式调用和它们需要的中断。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        time::init(machine);
+        ipi::init(machine);
+        for eid in LEGACY_SET_TIMER..=LEGACY_SHUTDOWN {
+            register(eid, self);
+        }
+    }
+}
+
+impl Extension for Legacy {
+    fn handle(&self, eid: usize, _fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let a0 = ctx.a(0);
+        match eid {
+            LEGACY_SET_TIMER => time::set_timer(a0 as _),
+            LEGACY_CONSOLE_PUTCHAR => {
+                console::write_raw(&[a0 as u8]);
+                SbiRet::success(0)
+            }
+            LEGACY_CONSOLE_GETCHAR => {
+                SbiRet::success(console::rx::get_char().map_or(usize::MAX, usize::from))
+            }
+            LEGACY_CLEAR_IPI => {
+                unsafe { asm!("csrc mip, {}", in(reg) MIP_SSIP) };
+                SbiRet::success(0)
+            }
+            LEGACY_SEND_IPI => remote(a0, ipi::SSIP),
+            LEGACY_REMOTE_FENCE_I => remote(a0, ipi::FENCE_I),
+            LEGACY_REMOTE_SFENCE_VMA | LEGACY_REMOTE_SFENCE_VMA_ASID => remote(a0, ipi::SFENCE_VMA),
+            LEGACY_SHUTDOWN => (self.shutdown)(),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        time::init_hart();
+        ipi::init_hart();
+    }
+
+    #[inline]
+    fn legacy(&self) -> bool {
+        true
+    }
+}
+
+/// 向 `hart_mask_ptr` 指向的掩码中的硬件线程发出请求。
+///
+/// 掩码位于 S 态虚存中，地址为 0 表示所有硬件线程，不可用的硬件线程被忽略。
+fn remote(hart_mask_ptr: usize, what: usize) -> SbiRet {
+    let mask = if hart_mask_ptr == 0 {
+        usize::MAX
+    } else {
+        match read_supervisor(hart_mask_ptr) {
+            Ok(mask) => mask,
+            Err(_) => return SbiRet::invalid_address(),
+        }
+    } & ipi::available();
+    if !ipi::exists() {
+        return SbiRet::not_supported();
+    }
+    let this = hartid();
+    let targets = (0..usize::BITS as usize).filter(|hart| mask >> hart & 1 == 1);
+    if what == ipi::SSIP {
+        targets.for_each(ipi::send_supervisor);
+        return SbiRet::success(0);
+    }
+    for hart in targets.clone() {
+        pmu::record(match what {
+            ipi::FENCE_I => pmu::FENCE_I_SENT,
+            _ => pmu::SFENCE_VMA_SENT,
+        });
+        if hart == this {
+            ipi::perform(what);
+        } else {
+            ipi::request(hart, what);
+        }
+    }
+    for hart in targets.filter(|hart| *hart != this) {
+        ipi::wait(hart, what);
+    }
+    SbiRet::success(0)
+}
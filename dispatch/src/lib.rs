@@ -0,0 +1,76 @@
+//! 这个项目实现机器态的陷入处理和 SBI 调用分发。
+//!
+//! 来自 S 态的 `ecall` 按 SBI §3 的二进制编码解析：`a7` 是扩展号，`a6` 是功能号，
+//! 调用交给注册的 [`Extension`]，结果 [`SbiRet`](sbi_spec::binary::SbiRet) 写回 `a0`/`a1`。
+//! 没有注册的扩展返回 `NOT_SUPPORTED`。
+//!
+//! 各章启动时先调用 [`boot`] 完成公共的初始化，再注册自己实现的扩展，[`Base`] 的 `probe_extension` 也查询同一个注册表。
+
+#![no_std]
+#![feature(naked_functions, asm_const, fn_align)]
+#![deny(warnings, missing_docs)]
+
+mod base;
+mod boot;
+mod clint;
+mod cppc;
+mod dbcn;
+mod fwft;
+mod hsm;
+mod ipi;
+mod legacy;
+mod memory;
+mod mpxy;
+mod nacl;
+mod pmu;
+mod registry;
+mod rfence;
+mod spi;
+mod srst;
+mod sta;
+mod susp;
+mod time;
+mod trap;
+mod vendor;
+
+pub use base::Base;
+pub use boot::boot;
+pub use cppc::Cppc;
+pub use dbcn::Dbcn;
+pub use fwft::Fwft;
+pub use hsm::Hsm;
+pub use legacy::Legacy;
+pub use mpxy::Mpxy;
+pub use nacl::Nacl;
+pub use pmu::Pmu;
+pub use registry::{calls, probe, register, Extension};
+pub use rfence::Rfence;
+pub use spi::Ipi;
+pub use srst::Srst;
+pub use sta::Sta;
+pub use susp::Susp;
+pub use time::Time;
+pub use trap::{
+    enter_supervisor, init, read_supervisor, register_interrupt, TrapContext, MACHINE_EXTERNAL,
+    MACHINE_SOFT, MACHINE_TIMER,
+};
+pub use vendor::{BuildInfo, Vendor};
+
+use sbi_spec::binary::{SbiRet, RET_ERR_DENIED};
+
+/// 返回 `DENIED`，`sbi-spec` 0.0.4 没有提供对应的构造函数。
+#[inline]
+pub(crate) const fn denied() -> SbiRet {
+    SbiRet {
+        error: RET_ERR_DENIED,
+        value: 0,
+    }
+}
+
+/// 当前硬件线程号。
+#[inline]
+pub fn hartid() -> usize {
+    let ans: usize;
+    unsafe { core::arch::asm!("csrr {}, mhartid", out(reg) ans) };
+    ans
+}
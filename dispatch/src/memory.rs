@@ -0,0 +1,25 @@
+//! 各扩展共用的物理内存范围。
+//!
+//! S 态传来的物理地址都要在这里检查，只由 [`boot`](crate::boot) 记录一次。
+
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+
+static START: AtomicUsize = AtomicUsize::new(0);
+static END: AtomicUsize = AtomicUsize::new(0);
+
+/// 记录物理内存范围。
+///
+/// 其他硬件线程在引导硬件线程完成初始化之后才会读取，不需要更强的内存序。
+pub(crate) fn init(mem: &Range<usize>) {
+    START.store(mem.start, Relaxed);
+    END.store(mem.end, Relaxed);
+}
+
+/// S 态可以访问的物理内存，记录之前为空。
+#[inline]
+pub(crate) fn range() -> Range<usize> {
+    START.load(Relaxed)..END.load(Relaxed)
+}
@@ -0,0 +1,355 @@
+//! SBI §20 MPXY 扩展。
+//!
+//! S 态为每个硬件线程指定一块 [`SHMEM_SIZE`] 字节的共享内存，通道号列表、属性值和消息都通过它传递，
+//! 以小端 32 位字为单位。
+//!
+//! 固件自己提供两个通道，不需要平台微控制器：
+//!
+//! - 通道 0 是回环通道，带响应发送时原样返回消息；
+//! - 通道 1 是 RPMI 风格的测试通道，实现 BASE 服务组的几个查询服务。
+
+use crate::{denied, hartid, memory, register, Extension, TrapContext};
+use core::ops::Range;
+use machine_info::MAX_HARTS;
+use sbi_spec::binary::SbiRet;
+
+/// MPXY 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_MPXY: usize = 0x4d505859;
+const GET_SHMEM_SIZE: usize = 0;
+const SET_SHMEM: usize = 1;
+const GET_CHANNEL_IDS: usize = 2;
+const READ_ATTRIBUTES: usize = 3;
+const WRITE_ATTRIBUTES: usize = 4;
+const SEND_MESSAGE_WITH_RESPONSE: usize = 5;
+const SEND_MESSAGE_WITHOUT_RESPONSE: usize = 6;
+const GET_NOTIFICATION_EVENTS: usize = 7;
+
+/// 没有设置共享内存，SBI 2.0 新增的错误码。
+const RET_ERR_NO_SHMEM: usize = -9isize as _;
+/// 属性号超出范围，SBI 3.0 新增的错误码。
+const RET_ERR_BAD_RANGE: usize = -11isize as _;
+
+/// 共享内存的大小和对齐。
+const SHMEM_SIZE: usize = 4096;
+/// 共享内存能容纳的字数。
+const SHMEM_WORDS: usize = SHMEM_SIZE / 4;
+/// 共享内存地址的低位和高位都是这个值时，停止使用共享内存。
+const SHMEM_DISABLE: usize = usize::MAX;
+/// 替换共享内存，并把原来的地址写入新的共享内存。
+const SHMEM_OVERWRITE_RETURN: usize = 1;
+
+// 标准属性。
+const MSG_PROT_ID: u32 = 0;
+const MSG_PROT_VERSION: u32 = 1;
+const MSG_MAX_LEN: u32 = 2;
+const MSG_SEND_TIMEOUT: u32 = 3;
+const MSG_COMPLETION_TIMEOUT: u32 = 4;
+const CHANNEL_CAPABILITY: u32 = 5;
+const SSE_EVENT_ID: u32 = 6;
+const MSI_CONTROL: u32 = 7;
+const MSI_ADDR_LO: u32 = 8;
+const MSI_ADDR_HI: u32 = 9;
+const MSI_DATA: u32 = 10;
+const EVENTS_STATE_CONTROL: u32 = 11;
+/// 消息协议定义的属性从这里开始。
+const PROTOCOL_ATTRIBUTES: u32 = 0x8000_0000;
+
+// 通道能力。
+const CAPABILITY_SEND_WITH_RESPONSE: u32 = 1 << 3;
+const CAPABILITY_SEND_WITHOUT_RESPONSE: u32 = 1 << 4;
+
+// 消息协议号，厂商定义的协议从 0x80000000 开始。
+const PROTOCOL_RPMI: u32 = 0;
+const PROTOCOL_LOOPBACK: u32 = 0x8000_0000;
+
+// RPMI 协议定义的属性。
+const RPMI_SERVICEGROUP_ID: u32 = PROTOCOL_ATTRIBUTES;
+const RPMI_SERVICEGROUP_VERSION: u32 = PROTOCOL_ATTRIBUTES + 1;
+const RPMI_IMPL_ID: u32 = PROTOCOL_ATTRIBUTES + 2;
+const RPMI_IMPL_VERSION: u32 = PROTOCOL_ATTRIBUTES + 3;
+
+// RPMI BASE 服务组的服务。
+const RPMI_SERVICEGROUP_BASE: u32 = 1;
+const RPMI_GET_IMPLEMENTATION_VERSION: usize = 2;
+const RPMI_GET_IMPLEMENTATION_IDN: usize = 3;
+const RPMI_GET_SPEC_VERSION: usize = 4;
+const RPMI_PROBE_SERVICE_GROUP: usize = 6;
+
+// RPMI 状态码。
+const RPMI_SUCCESS: u32 = 0;
+const RPMI_ERR_NOT_SUPPORTED: u32 = -2i32 as _;
+const RPMI_ERR_INVALID_PARAM: u32 = -3i32 as _;
+
+/// RPMI 规范版本 1.0。
+const RPMI_VERSION: u32 = 1 << 16;
+/// 测试通道报告的实现号和版本。
+const IMPL_ID: u32 = 0;
+const IMPL_VERSION: u32 = 1 << 16;
+
+/// 每个硬件线程的共享内存地址，0 表示没有设置。
+static mut SHMEM: [usize; MAX_HARTS] = [0; MAX_HARTS];
+/// 每个通道的 `MSI_ADDR_LO`、`MSI_ADDR_HI` 和 `MSI_DATA`，不支持 MSI，只保存写入的值。
+static mut MSI: [[u32; 3]; CHANNELS.len()] = [[0; 3]; CHANNELS.len()];
+
+/// 固件提供的通道，通道号是下标。
+const CHANNELS: [Channel; 2] = [Channel::Loopback, Channel::RpmiBase];
+
+#[derive(Clone, Copy)]
+enum Channel {
+    Loopback,
+    RpmiBase,
+}
+
+/// MPXY 扩展。
+pub struct Mpxy;
+
+impl Mpxy {
+    /// 注册 MPXY 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self) {
+        register(EID_MPXY, self);
+    }
+}
+
+impl Extension for Mpxy {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            GET_SHMEM_SIZE => return SbiRet::success(SHMEM_SIZE),
+            SET_SHMEM => return set_shmem(ctx.a(0), ctx.a(1), ctx.a(2)),
+            GET_CHANNEL_IDS..=GET_NOTIFICATION_EVENTS => {}
+            _ => return SbiRet::not_supported(),
+        }
+        let Some(shmem) = shmem() else {
+            return SbiRet {
+                error: RET_ERR_NO_SHMEM,
+                value: 0,
+            };
+        };
+        if fid == GET_CHANNEL_IDS {
+            return get_channel_ids(shmem, ctx.a(0));
+        }
+        let id = ctx.a(0);
+        let Some(&channel) = CHANNELS.get(id) else {
+            return SbiRet::not_supported();
+        };
+        match fid {
+            READ_ATTRIBUTES => read_attributes(shmem, id, ctx.a(1), ctx.a(2)),
+            WRITE_ATTRIBUTES => write_attributes(shmem, id, ctx.a(1), ctx.a(2)),
+            SEND_MESSAGE_WITH_RESPONSE | SEND_MESSAGE_WITHOUT_RESPONSE => {
+                let len = ctx.a(2);
+                if len > SHMEM_SIZE {
+                    return SbiRet::invalid_param();
+                }
+                let response = channel.send(shmem, ctx.a(1), len);
+                if fid == SEND_MESSAGE_WITH_RESPONSE {
+                    SbiRet::success(response)
+                } else {
+                    SbiRet::success(0)
+                }
+            }
+            // 没有通知，剩余、返回和丢失的事件数都是 0
+            _ => {
+                (0..4).for_each(|i| shmem.write(i, 0));
+                SbiRet::success(0)
+            }
+        }
+    }
+
+    fn init_hart(&self, hartid: usize) {
+        unsafe { SHMEM[hartid] = 0 };
+    }
+}
+
+/// 一块共享内存。
+#[derive(Clone, Copy)]
+struct Shmem(*mut u32);
+
+impl Shmem {
+    #[inline]
+    fn read(self, i: usize) -> u32 {
+        u32::from_le(unsafe { self.0.add(i).read_volatile() })
+    }
+
+    #[inline]
+    fn write(self, i: usize, value: u32) {
+        unsafe { self.0.add(i).write_volatile(value.to_le()) }
+    }
+}
+
+/// 当前硬件线程的共享内存。
+#[inline]
+fn shmem() -> Option<Shmem> {
+    match unsafe { SHMEM[hartid()] } {
+        0 => None,
+        addr => Some(Shmem(addr as _)),
+    }
+}
+
+fn set_shmem(lo: usize, hi: usize, flags: usize) -> SbiRet {
+    if flags > SHMEM_OVERWRITE_RETURN {
+        return SbiRet::invalid_param();
+    }
+    let hartid = hartid();
+    let old = unsafe { SHMEM[hartid] };
+    if lo == SHMEM_DISABLE && hi == SHMEM_DISABLE {
+        unsafe { SHMEM[hartid] = 0 };
+        return SbiRet::success(0);
+    }
+    if lo % SHMEM_SIZE != 0 {
+        return SbiRet::invalid_param();
+    }
+    let memory = memory::range();
+    if hi != 0 || lo < memory.start || lo.saturating_add(SHMEM_SIZE) > memory.end {
+        return SbiRet::invalid_address();
+    }
+    if flags == SHMEM_OVERWRITE_RETURN {
+        // 原来没有共享内存时写入全 1
+        let (old_lo, old_hi) = match old {
+            0 => (SHMEM_DISABLE, SHMEM_DISABLE),
+            old => (old, 0),
+        };
+        let new = lo as *mut usize;
+        unsafe {
+            new.write_volatile(old_lo.to_le());
+            new.add(1).write_volatile(old_hi.to_le());
+        }
+    }
+    unsafe { SHMEM[hartid] = lo };
+    SbiRet::success(0)
+}
+
+/// 从 `start_index` 开始列出通道号，前两个字是剩余和本次返回的个数。
+fn get_channel_ids(shmem: Shmem, start_index: usize) -> SbiRet {
+    if start_index > CHANNELS.len() {
+        return SbiRet::invalid_param();
+    }
+    let ids = (start_index..CHANNELS.len()).take(SHMEM_WORDS - 2);
+    let returned = ids.len();
+    for (i, id) in ids.enumerate() {
+        shmem.write(2 + i, id as _);
+    }
+    shmem.write(0, (CHANNELS.len() - start_index - returned) as _);
+    shmem.write(1, returned as _);
+    SbiRet::success(0)
+}
+
+/// 检查属性范围，返回属性号。
+fn attributes(base: usize, count: usize) -> Result<Range<u32>, SbiRet> {
+    if count == 0 || count > SHMEM_WORDS || base > u32::MAX as usize {
+        return Err(SbiRet::invalid_param());
+    }
+    let base = base as u32;
+    match base.checked_add(count as u32) {
+        Some(end) => Ok(base..end),
+        None => Err(SbiRet::invalid_param()),
+    }
+}
+
+fn read_attributes(shmem: Shmem, id: usize, base: usize, count: usize) -> SbiRet {
+    let ids = match attributes(base, count) {
+        Ok(ids) => ids,
+        Err(err) => return err,
+    };
+    let msi = unsafe { MSI[id] };
+    // 先检查所有属性，失败时不改变共享内存
+    let value = |attr| match attr {
+        MSI_ADDR_LO | MSI_ADDR_HI | MSI_DATA => Some(msi[(attr - MSI_ADDR_LO) as usize]),
+        _ => CHANNELS[id].attribute(attr),
+    };
+    if ids.clone().any(|attr| value(attr).is_none()) {
+        return bad_range();
+    }
+    for (i, attr) in ids.enumerate() {
+        shmem.write(i, value(attr).unwrap());
+    }
+    SbiRet::success(0)
+}
+
+fn write_attributes(shmem: Shmem, id: usize, base: usize, count: usize) -> SbiRet {
+    let ids = match attributes(base, count) {
+        Ok(ids) => ids,
+        Err(err) => return err,
+    };
+    // 只有 MSI 和事件状态的控制属性可写，不支持的功能只能写 0
+    for (i, attr) in ids.clone().enumerate() {
+        match attr {
+            MSI_CONTROL | EVENTS_STATE_CONTROL if shmem.read(i) != 0 => {
+                return SbiRet::invalid_param()
+            }
+            MSI_CONTROL..=EVENTS_STATE_CONTROL => {}
+            _ if CHANNELS[id].attribute(attr).is_some() => return denied(),
+            _ => return bad_range(),
+        }
+    }
+    for (i, attr) in ids.enumerate() {
+        if let MSI_ADDR_LO..=MSI_DATA = attr {
+            unsafe { MSI[id][(attr - MSI_ADDR_LO) as usize] = shmem.read(i) };
+        }
+    }
+    SbiRet::success(0)
+}
+
+#[inline]
+fn bad_range() -> SbiRet {
+    SbiRet {
+        error: RET_ERR_BAD_RANGE,
+        value: 0,
+    }
+}
+
+impl Channel {
+    /// 只读属性的值，MSI 和事件状态相关的可写属性另外处理。
+    fn attribute(self, attr: u32) -> Option<u32> {
+        let protocol = match self {
+            Self::Loopback => PROTOCOL_LOOPBACK,
+            Self::RpmiBase => PROTOCOL_RPMI,
+        };
+        match (attr, self) {
+            (MSG_PROT_ID, _) => Some(protocol),
+            (MSG_PROT_VERSION, Self::Loopback) => Some(1 << 16),
+            (MSG_PROT_VERSION, Self::RpmiBase) => Some(RPMI_VERSION),
+            (MSG_MAX_LEN, _) => Some(SHMEM_SIZE as _),
+            // 消息在调用期间处理完毕
+            (MSG_SEND_TIMEOUT | MSG_COMPLETION_TIMEOUT, _) => Some(0),
+            (CHANNEL_CAPABILITY, _) => {
+                Some(CAPABILITY_SEND_WITH_RESPONSE | CAPABILITY_SEND_WITHOUT_RESPONSE)
+            }
+            (SSE_EVENT_ID, _) => Some(0),
+            (MSI_CONTROL | EVENTS_STATE_CONTROL, _) => Some(0),
+            (RPMI_SERVICEGROUP_ID, Self::RpmiBase) => Some(RPMI_SERVICEGROUP_BASE),
+            (RPMI_SERVICEGROUP_VERSION, Self::RpmiBase) => Some(RPMI_VERSION),
+            (RPMI_IMPL_ID, Self::RpmiBase) => Some(IMPL_ID),
+            (RPMI_IMPL_VERSION, Self::RpmiBase) => Some(IMPL_VERSION),
+            _ => None,
+        }
+    }
+
+    /// 处理共享内存中长 `len` 字节的消息，把响应写回共享内存，返回响应的字节数。
+    fn send(self, shmem: Shmem, message_id: usize, len: usize) -> usize {
+        match self {
+            // 消息已经在共享内存里
+            Self::Loopback => len,
+            Self::RpmiBase => {
+                let arg = (len >= 4).then(|| shmem.read(0));
+                let response: &[u32] = match (message_id, arg) {
+                    (RPMI_GET_IMPLEMENTATION_VERSION, _) => &[RPMI_SUCCESS, IMPL_VERSION],
+                    (RPMI_GET_IMPLEMENTATION_IDN, _) => &[RPMI_SUCCESS, IMPL_ID],
+                    (RPMI_GET_SPEC_VERSION, _) => &[RPMI_SUCCESS, RPMI_VERSION],
+                    // 只有 BASE 服务组
+                    (RPMI_PROBE_SERVICE_GROUP, Some(RPMI_SERVICEGROUP_BASE)) => {
+                        &[RPMI_SUCCESS, RPMI_VERSION]
+                    }
+                    (RPMI_PROBE_SERVICE_GROUP, Some(_)) => &[RPMI_SUCCESS, 0],
+                    (RPMI_PROBE_SERVICE_GROUP, None) => &[RPMI_ERR_INVALID_PARAM],
+                    // 包括使能通知，没有通知
+                    _ => &[RPMI_ERR_NOT_SUPPORTED],
+                };
+                for (i, word) in response.iter().enumerate() {
+                    shmem.write(i, *word);
+                }
+                response.len() * 4
+            }
+        }
+    }
+}
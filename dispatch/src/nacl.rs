@@ -0,0 +1,339 @@
+//! SBI §15 NACL 扩展。
+//!
+//! 运行在 HS 态的虚拟机监控程序为每个硬件线程指定一块共享内存，布局为：
+//!
+//! | 偏移   | 大小   | 内容
+//! |:------:|:------:|-
+//! | 0x0000 | 0x200  | SRET 上下文，第 `i` 个字是 `x<i>`
+//! | 0x0200 | 0x80   | 自动交换的 CSR，不支持
+//! | 0x0800 | 0x780  | HFENCE 条目，每条 4 个字
+//! | 0x0f80 | 0x80   | CSR 脏位图
+//! | 0x1000 | 0x2000 | CSR 数组
+//!
+//! CSR 号 `csr` 在数组和脏位图中的序号是 `(csr & 0xc00) >> 2 | csr & 0xff`，
+//! 只有 H 扩展和 VS 态的 CSR 可以同步。
+//!
+//! 同步 CSR 把脏的 CSR 写入硬件，再把硬件的值读回数组；同步 HFENCE 执行所有挂起的条目。
+//! `sync_sret` 同步全部 CSR 和 HFENCE 后，从 SRET 上下文恢复通用寄存器，代替调用方执行 `sret`。
+
+use crate::{
+    hartid, memory, register,
+    rfence::{hfence_gvma, hfence_vvma, hgatp, with_hgatp, FLUSH_THRESHOLD, PAGE_SIZE},
+    Extension, TrapContext,
+};
+use core::{
+    arch::asm,
+    ptr::{addr_of, addr_of_mut},
+};
+use machine_info::{IsaExtensions, MachineInfo, MAX_HARTS};
+use sbi_spec::binary::SbiRet;
+
+/// NACL 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_NACL: usize = 0x4e41434c;
+const PROBE_FEATURE: usize = 0;
+const SET_SHMEM: usize = 1;
+const SYNC_CSR: usize = 2;
+const SYNC_HFENCE: usize = 3;
+const SYNC_SRET: usize = 4;
+
+// 特性号，不支持自动交换 CSR。
+const FEATURE_SYNC_CSR: usize = 0;
+const FEATURE_SYNC_HFENCE: usize = 1;
+const FEATURE_SYNC_SRET: usize = 2;
+
+/// 没有设置共享内存，SBI 2.0 新增的错误码。
+const RET_ERR_NO_SHMEM: usize = -9isize as _;
+
+/// 共享内存的对齐。
+const SHMEM_ALIGN: usize = 4096;
+/// 共享内存地址的低位和高位都是这个值时，停止使用共享内存。
+const SHMEM_DISABLE: usize = usize::MAX;
+/// 同步所有 CSR 或所有 HFENCE 条目。
+const ALL: usize = usize::MAX;
+
+/// HFENCE 条目数。
+const HFENCE_ENTRIES: usize = 0x780 / 32;
+/// CSR 数组的长度。
+const CSR_ENTRIES: usize = 1024;
+
+// HFENCE 条目配置字的各个字段。
+const HFENCE_PEND: usize = 1 << 63;
+const HFENCE_TYPE_SHIFT: usize = 56;
+const HFENCE_ORDER_SHIFT: usize = 48;
+const HFENCE_VMID_SHIFT: usize = 16;
+/// 页大小是 `1 << (ORDER + HFENCE_ORDER_BASE)`。
+const HFENCE_ORDER_BASE: usize = 12;
+
+// HFENCE 条目类型。
+const HFENCE_GVMA: usize = 0;
+const HFENCE_GVMA_ALL: usize = 1;
+const HFENCE_GVMA_VMID: usize = 2;
+const HFENCE_GVMA_VMID_ALL: usize = 3;
+const HFENCE_VVMA: usize = 4;
+const HFENCE_VVMA_ALL: usize = 5;
+const HFENCE_VVMA_ASID: usize = 6;
+const HFENCE_VVMA_ASID_ALL: usize = 7;
+
+/// `hgatp.VMID` 的位置。
+const HGATP_VMID_SHIFT: usize = 44;
+const HGATP_VMID: usize = 0x3fff << HGATP_VMID_SHIFT;
+
+const MSTATUS_SIE: usize = 1 << 1;
+const MSTATUS_SPIE: usize = 1 << 5;
+const MSTATUS_SPP: usize = 1 << 8;
+const MSTATUS_MPP: usize = 0b11 << 11;
+const MSTATUS_MPV: usize = 1 << 39;
+const MPP_SUPERVISOR: usize = 0b01 << 11;
+const HSTATUS_SPV: usize = 1 << 7;
+
+/// 共享内存的布局。
+#[repr(C)]
+struct Shmem {
+    sret: [usize; 64],
+    autoswap: [usize; 16],
+    unused: [usize; 176],
+    hfence: [[usize; 4]; HFENCE_ENTRIES],
+    dirty: [usize; CSR_ENTRIES / usize::BITS as usize],
+    csr: [usize; CSR_ENTRIES],
+}
+
+/// 每个硬件线程的共享内存地址，0 表示没有设置。
+static mut SHMEM: [usize; MAX_HARTS] = [0; MAX_HARTS];
+
+/// 定义可以同步的 CSR，以及按运行时的 CSR 号读写它们的函数。
+macro_rules! nested_csrs {
+    ($($csr:literal)*) => {
+        const CSRS: &[usize] = &[$($csr),*];
+
+        fn read_csr(csr: usize) -> usize {
+            match csr {
+                $($csr => {
+                    let value: usize;
+                    unsafe { asm!("csrr {}, {csr}", out(reg) value, csr = const $csr) };
+                    value
+                })*
+                _ => unreachable!(),
+            }
+        }
+
+        fn write_csr(csr: usize, value: usize) {
+            match csr {
+                $($csr => unsafe { asm!("csrw {csr}, {}", in(reg) value, csr = const $csr) },)*
+                _ => unreachable!(),
+            }
+        }
+    };
+}
+
+// vsstatus vsie vstvec vsscratch vsepc vscause vstval vsip vsatp
+// hstatus hedeleg hideleg hie htimedelta hcounteren hgeie henvcfg htval hip hvip htinst hgatp
+nested_csrs! {
+    0x200 0x204 0x205 0x240 0x241 0x242 0x243 0x244 0x280
+    0x600 0x602 0x603 0x604 0x605 0x606 0x607 0x60a 0x643 0x644 0x645 0x64a 0x680
+}
+
+/// NACL 扩展。
+pub struct Nacl;
+
+impl Nacl {
+    /// 注册 NACL 扩展。不是所有硬件线程都支持虚拟化扩展时不注册。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        if !machine.isa.contains(IsaExtensions::H) {
+            return;
+        }
+        register(EID_NACL, self);
+    }
+}
+
+impl Extension for Nacl {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            PROBE_FEATURE => SbiRet::success(matches!(
+                ctx.a(0),
+                FEATURE_SYNC_CSR | FEATURE_SYNC_HFENCE | FEATURE_SYNC_SRET
+            ) as _),
+            SET_SHMEM => set_shmem(ctx.a(0), ctx.a(1), ctx.a(2)),
+            SYNC_CSR => match shmem() {
+                Some(shmem) => sync_csr(shmem, ctx.a(0)),
+                None => no_shmem(),
+            },
+            SYNC_HFENCE => match shmem() {
+                Some(shmem) => sync_hfence(shmem, ctx.a(0)),
+                None => no_shmem(),
+            },
+            SYNC_SRET => match shmem() {
+                Some(shmem) => sync_sret(shmem, ctx),
+                None => no_shmem(),
+            },
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, hartid: usize) {
+        unsafe { SHMEM[hartid] = 0 };
+    }
+}
+
+#[inline]
+fn no_shmem() -> SbiRet {
+    SbiRet {
+        error: RET_ERR_NO_SHMEM,
+        value: 0,
+    }
+}
+
+/// 当前硬件线程的共享内存。
+#[inline]
+fn shmem() -> Option<*mut Shmem> {
+    match unsafe { SHMEM[hartid()] } {
+        0 => None,
+        addr => Some(addr as _),
+    }
+}
+
+/// CSR 在数组和脏位图中的序号。
+#[inline]
+fn index(csr: usize) -> usize {
+    (csr & 0xc00) >> 2 | csr & 0xff
+}
+
+fn set_shmem(lo: usize, hi: usize, flags: usize) -> SbiRet {
+    if flags != 0 {
+        return SbiRet::invalid_param();
+    }
+    let hartid = hartid();
+    if lo == SHMEM_DISABLE && hi == SHMEM_DISABLE {
+        unsafe { SHMEM[hartid] = 0 };
+        return SbiRet::success(0);
+    }
+    if lo % SHMEM_ALIGN != 0 {
+        return SbiRet::invalid_param();
+    }
+    let memory = memory::range();
+    let size = core::mem::size_of::<Shmem>();
+    if hi != 0 || lo < memory.start || lo.saturating_add(size) > memory.end {
+        return SbiRet::invalid_address();
+    }
+    // 数组从硬件的当前值开始，没有脏的 CSR
+    let shmem = lo as *mut Shmem;
+    unsafe {
+        for i in 0..CSR_ENTRIES / usize::BITS as usize {
+            addr_of_mut!((*shmem).dirty[i]).write_volatile(0);
+        }
+        for &csr in CSRS {
+            addr_of_mut!((*shmem).csr[index(csr)]).write_volatile(read_csr(csr));
+        }
+        SHMEM[hartid] = lo;
+    }
+    SbiRet::success(0)
+}
+
+fn sync_csr(shmem: *mut Shmem, csr_num: usize) -> SbiRet {
+    if csr_num != ALL && !CSRS.contains(&csr_num) {
+        return SbiRet::invalid_param();
+    }
+    for &csr in CSRS.iter().filter(|csr| csr_num == ALL || **csr == csr_num) {
+        let i = index(csr);
+        let bits = usize::BITS as usize;
+        unsafe {
+            let dirty = addr_of_mut!((*shmem).dirty[i / bits]);
+            let value = addr_of_mut!((*shmem).csr[i]);
+            if dirty.read_volatile() >> (i % bits) & 1 == 1 {
+                write_csr(csr, value.read_volatile());
+                dirty.write_volatile(dirty.read_volatile() & !(1 << (i % bits)));
+            }
+            // 硬件可能只接受部分位，也可能自行改变了值
+            value.write_volatile(read_csr(csr));
+        }
+    }
+    SbiRet::success(0)
+}
+
+fn sync_hfence(shmem: *mut Shmem, entry_index: usize) -> SbiRet {
+    let entries = match entry_index {
+        ALL => 0..HFENCE_ENTRIES,
+        i if i < HFENCE_ENTRIES => i..i + 1,
+        _ => return SbiRet::invalid_param(),
+    };
+    for i in entries {
+        let entry = unsafe { addr_of_mut!((*shmem).hfence[i]) };
+        let [config, pnum, pcount, _] = unsafe { entry.read_volatile() };
+        if config & HFENCE_PEND == 0 {
+            continue;
+        }
+        hfence(config, pnum, pcount);
+        unsafe { addr_of_mut!((*entry)[0]).write_volatile(config & !HFENCE_PEND) };
+    }
+    SbiRet::success(0)
+}
+
+/// 执行一个 HFENCE 条目，`pnum` 和 `pcount` 以条目给出的页大小为单位。
+///
+/// 和 RFENCE 一样，超过 [`FLUSH_THRESHOLD`] 对应页数的范围改为全部刷新，
+/// 页大小超出地址宽度时也是如此。
+fn hfence(config: usize, pnum: usize, pcount: usize) {
+    let kind = config >> HFENCE_TYPE_SHIFT & 0xf;
+    let order = (config >> HFENCE_ORDER_SHIFT & 0x7f) + HFENCE_ORDER_BASE;
+    let vmid = config >> HFENCE_VMID_SHIFT & 0x3fff;
+    let asid = config & 0xffff;
+    let all = pcount > FLUSH_THRESHOLD / PAGE_SIZE || order >= usize::BITS as usize;
+    let pages = |f: &dyn Fn(Option<usize>)| {
+        (pnum..pnum.saturating_add(pcount)).for_each(|page| f(Some(page << order)))
+    };
+    let with_vmid = |f: &dyn Fn()| with_hgatp(hgatp() & !HGATP_VMID | vmid << HGATP_VMID_SHIFT, f);
+    match kind {
+        HFENCE_GVMA if all => hfence_gvma(None, None),
+        HFENCE_GVMA => pages(&|addr| hfence_gvma(addr, None)),
+        HFENCE_GVMA_ALL => hfence_gvma(None, None),
+        HFENCE_GVMA_VMID if all => hfence_gvma(None, Some(vmid)),
+        HFENCE_GVMA_VMID => pages(&|addr| hfence_gvma(addr, Some(vmid))),
+        HFENCE_GVMA_VMID_ALL => hfence_gvma(None, Some(vmid)),
+        HFENCE_VVMA if all => with_vmid(&|| hfence_vvma(None, None)),
+        HFENCE_VVMA => with_vmid(&|| pages(&|addr| hfence_vvma(addr, None))),
+        HFENCE_VVMA_ALL => with_vmid(&|| hfence_vvma(None, None)),
+        HFENCE_VVMA_ASID if all => with_vmid(&|| hfence_vvma(None, Some(asid))),
+        HFENCE_VVMA_ASID => with_vmid(&|| pages(&|addr| hfence_vvma(addr, Some(asid)))),
+        HFENCE_VVMA_ASID_ALL => with_vmid(&|| hfence_vvma(None, Some(asid))),
+        // 保留的类型忽略
+        _ => {}
+    }
+}
+
+/// 同步后代替调用方执行 `sret`，成功时不返回调用处。
+///
+/// 返回值写回 `a0`/`a1`，所以成功时返回从 SRET 上下文恢复的 `a0`/`a1`。
+fn sync_sret(shmem: *mut Shmem, ctx: &mut TrapContext) -> SbiRet {
+    sync_csr(shmem, ALL);
+    sync_hfence(shmem, ALL);
+    for (i, x) in ctx.x.iter_mut().enumerate().skip(1) {
+        *x = unsafe { addr_of!((*shmem).sret[i]).read_volatile() };
+    }
+    let mstatus: usize;
+    let hstatus: usize;
+    let sepc: usize;
+    unsafe {
+        asm!("csrr {}, mstatus", out(reg) mstatus);
+        asm!("csrr {}, 0x600", out(reg) hstatus);
+        asm!("csrr {}, sepc", out(reg) sepc);
+    }
+    // sret：特权级来自 SPP，V 来自 hstatus.SPV，SIE 来自 SPIE
+    let mut status =
+        mstatus & !(MSTATUS_SIE | MSTATUS_SPP | MSTATUS_MPP | MSTATUS_MPV) | MSTATUS_SPIE;
+    if mstatus & MSTATUS_SPIE != 0 {
+        status |= MSTATUS_SIE;
+    }
+    if mstatus & MSTATUS_SPP != 0 {
+        status |= MPP_SUPERVISOR;
+    }
+    if hstatus & HSTATUS_SPV != 0 {
+        status |= MSTATUS_MPV;
+    }
+    unsafe { asm!("csrw mstatus, {}", in(reg) status) };
+    ctx.mepc = sepc;
+    SbiRet {
+        error: ctx.a(0),
+        value: ctx.a(1),
+    }
+}
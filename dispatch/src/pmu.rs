@@ -0,0 +1,398 @@
+//! SBI §11 PMU 扩展。
+//!
+//! 计数器的逻辑编号先排硬件计数器，再排固件计数器：
+//!
+//! - 硬件计数器是 `mcycle`、`minstret` 和设备树 `riscv,pmu` 节点提到的 `mhpmcounter`，
+//!   事件到计数器的映射和 `mhpmevent` 的值也来自这个节点，见 [`PmuInfo`]；
+//! - 每个固件事件有一个固定的固件计数器，由固件在处理相应的陷入和请求时软件计数。
+//!
+//! 计数器的配置和启停状态按硬件线程记录，只由硬件线程自己修改。
+
+use crate::{hartid, register, Extension, TrapContext};
+use core::{arch::asm, ptr::addr_of};
+use machine_info::{IsaExtensions, MachineInfo, PmuInfo, MAX_HARTS};
+use sbi_spec::{binary::SbiRet, pmu::*};
+
+// 固件事件，见 SBI §11.5。
+
+/// 不对齐的读。
+pub(crate) const MISALIGNED_LOAD: usize = 0;
+/// 不对齐的写。
+pub(crate) const MISALIGNED_STORE: usize = 1;
+/// 读访问错误。
+pub(crate) const ACCESS_LOAD: usize = 2;
+/// 写访问错误。
+pub(crate) const ACCESS_STORE: usize = 3;
+/// 非法指令。
+pub(crate) const ILLEGAL_INSN: usize = 4;
+/// 设置定时器。
+pub(crate) const SET_TIMER: usize = 5;
+/// 发出的核间中断。
+pub(crate) const IPI_SENT: usize = 6;
+/// 收到的核间中断。
+pub(crate) const IPI_RECEIVED: usize = 7;
+/// 发出的远程 `fence.i`。
+pub(crate) const FENCE_I_SENT: usize = 8;
+/// 收到的远程 `fence.i`。
+pub(crate) const FENCE_I_RECEIVED: usize = 9;
+/// 发出的远程 `sfence.vma`。
+pub(crate) const SFENCE_VMA_SENT: usize = 10;
+/// 收到的远程 `sfence.vma`。
+pub(crate) const SFENCE_VMA_RECEIVED: usize = 11;
+/// 发出的远程带 ASID 的 `sfence.vma`。
+pub(crate) const SFENCE_VMA_ASID_SENT: usize = 12;
+/// 收到的远程带 ASID 的 `sfence.vma`。
+pub(crate) const SFENCE_VMA_ASID_RECEIVED: usize = 13;
+/// 发出的远程 `hfence.gvma`。
+pub(crate) const HFENCE_GVMA_SENT: usize = 14;
+/// 收到的远程 `hfence.gvma`。
+pub(crate) const HFENCE_GVMA_RECEIVED: usize = 15;
+/// 发出的远程带 VMID 的 `hfence.gvma`。
+pub(crate) const HFENCE_GVMA_VMID_SENT: usize = 16;
+/// 收到的远程带 VMID 的 `hfence.gvma`。
+pub(crate) const HFENCE_GVMA_VMID_RECEIVED: usize = 17;
+/// 发出的远程 `hfence.vvma`。
+pub(crate) const HFENCE_VVMA_SENT: usize = 18;
+/// 收到的远程 `hfence.vvma`。
+pub(crate) const HFENCE_VVMA_RECEIVED: usize = 19;
+/// 发出的远程带 ASID 的 `hfence.vvma`。
+pub(crate) const HFENCE_VVMA_ASID_SENT: usize = 20;
+/// 收到的远程带 ASID 的 `hfence.vvma`。
+pub(crate) const HFENCE_VVMA_ASID_RECEIVED: usize = 21;
+/// 固件事件数。
+const FW_EVENTS: usize = 22;
+
+// 事件类型，即事件号的第 16 到 19 位。
+const TYPE_HARDWARE: usize = 0;
+const TYPE_CACHE: usize = 1;
+const TYPE_RAW: usize = 2;
+const TYPE_FIRMWARE: usize = 0xf;
+
+// 通用硬件事件中有固定计数器的两个。
+const HW_CPU_CYCLES: usize = 1;
+const HW_INSTRUCTIONS: usize = 2;
+
+// counter_config_matching 的标志。
+const CFG_FLAG_SKIP_MATCH: usize = 1 << 0;
+const CFG_FLAG_CLEAR_VALUE: usize = 1 << 1;
+const CFG_FLAG_AUTO_START: usize = 1 << 2;
+/// `SET_VUINH` 到 `SET_MINH` 这 5 位，依次对应 `mhpmevent` 的第 58 到 62 位。
+const CFG_FLAG_INHIBIT: usize = 0x1f << 3;
+const MHPMEVENT_INHIBIT_SHIFT: usize = 58;
+
+// counter_start 和 counter_stop 的标志。
+const START_FLAG_SET_INIT_VALUE: usize = 1 << 0;
+const STOP_FLAG_RESET: usize = 1 << 0;
+
+/// 以运行时的 CSR 序号 `$i` 执行 `$body`，`$body` 中的常量 `$n` 是这个序号。
+///
+/// CSR 地址是指令的立即数，只能为每个序号生成一个分支。
+macro_rules! for_csr {
+    ($i:expr, |$n:ident| $body:expr) => {
+        for_csr!(@ $i, $n, $body;
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+            16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31)
+    };
+    (@ $i:expr, $n:ident, $body:expr; $($k:literal)*) => {
+        match $i {
+            $($k => {
+                const $n: usize = $k;
+                $body
+            })*
+            _ => unreachable!(),
+        }
+    };
+}
+
+/// 计数器 CSR 的宽度。
+const COUNTER_WIDTH: usize = 64;
+
+/// 硬件计数器的 CSR 序号，`mcycle` 是 0，`minstret` 是 2，`mhpmcounter<n>` 是 `n`。
+static mut HARDWARE: [usize; 32] = [0; 32];
+/// 硬件计数器数。
+static mut HARDWARE_LEN: usize = 0;
+/// 事件映射。
+static mut INFO: PmuInfo = PmuInfo::NONE;
+/// 是否支持按特权级过滤。
+static mut SSCOFPMF: bool = false;
+
+/// 每个硬件线程的计数器状态，位图按逻辑编号排列。
+#[derive(Clone, Copy)]
+struct HartPmu {
+    configured: u64,
+    started: u64,
+    firmware: [u64; FW_EVENTS],
+}
+
+static mut HARTS: [HartPmu; MAX_HARTS] = [HartPmu {
+    configured: 0,
+    started: 0,
+    firmware: [0; FW_EVENTS],
+}; MAX_HARTS];
+
+/// PMU 扩展。
+pub struct Pmu;
+
+impl Pmu {
+    /// 注册 PMU 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        unsafe {
+            INFO = machine.pmu;
+            SSCOFPMF = machine.isa.contains(IsaExtensions::SSCOFPMF);
+            // time 不是可以配置的计数器
+            let csrs = machine.pmu.all_counters() | 0b101;
+            for csr in (0..32).filter(|i| i != &1 && csrs >> i & 1 == 1) {
+                HARDWARE[HARDWARE_LEN] = csr;
+                HARDWARE_LEN += 1;
+            }
+        }
+        register(EID_PMU, self);
+    }
+}
+
+impl Extension for Pmu {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let (a0, a1, a2, a3) = (ctx.a(0), ctx.a(1), ctx.a(2), ctx.a(3));
+        match fid {
+            PMU_NUM_COUNTERS => SbiRet::success(counters()),
+            PMU_COUNTER_GET_INFO => get_info(a0),
+            PMU_COUNTER_CONFIG_MATCHING => config_matching(a0, a1, a2, a3, ctx.a(4)),
+            PMU_COUNTER_START => start(a0, a1, a2, a3),
+            PMU_COUNTER_STOP => stop(a0, a1, a2),
+            PMU_COUNTER_FW_READ => fw_read(a0),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        // 未启动的 mhpmcounter 不计数，mcycle 和 minstret 保持运行
+        let mask = hardware()
+            .filter(|csr| *csr >= 3)
+            .fold(0, |acc, csr| acc | 1 << csr);
+        unsafe { asm!("csrs mcountinhibit, {}", in(reg) mask) };
+    }
+}
+
+/// 记录一次固件事件，只有启动的固件计数器计数。
+pub(crate) fn record(event: usize) {
+    let hart = unsafe { &mut HARTS[hartid()] };
+    if hart.started >> (hardware_len() + event) & 1 == 1 {
+        hart.firmware[event] += 1;
+    }
+}
+
+#[inline]
+fn hardware_len() -> usize {
+    unsafe { HARDWARE_LEN }
+}
+
+#[inline]
+fn hardware() -> impl Iterator<Item = usize> + Clone {
+    unsafe { HARDWARE[..HARDWARE_LEN].iter().copied() }
+}
+
+#[inline]
+fn counters() -> usize {
+    hardware_len() + FW_EVENTS
+}
+
+/// 逻辑编号为 `idx` 的计数器，硬件计数器返回 CSR 序号，固件计数器返回事件号。
+enum Counter {
+    Hardware(usize),
+    Firmware(usize),
+}
+
+fn counter(idx: usize) -> Counter {
+    match idx.checked_sub(hardware_len()) {
+        None => Counter::Hardware(unsafe { HARDWARE[idx] }),
+        Some(event) => Counter::Firmware(event),
+    }
+}
+
+/// 把 `counter_idx_mask` 和 `counter_idx_base` 转换为逻辑编号的位图，包含无效的计数器时返回 `None`。
+fn decode_mask(mask: usize, base: usize) -> Option<u64> {
+    if mask == 0 {
+        return Some(0);
+    }
+    let highest = (usize::BITS - 1 - mask.leading_zeros()) as usize;
+    match base.checked_add(highest) {
+        Some(last) if last < counters() => Some((mask as u64) << base),
+        _ => None,
+    }
+}
+
+fn each(mask: u64) -> impl Iterator<Item = usize> {
+    (0..u64::BITS as usize).filter(move |i| mask >> i & 1 == 1)
+}
+
+fn get_info(idx: usize) -> SbiRet {
+    if idx >= counters() {
+        return SbiRet::invalid_param();
+    }
+    SbiRet::success(match counter(idx) {
+        // S 态通过 cycle、instret 和 hpmcounter<n> 读取
+        Counter::Hardware(csr) => (0xc00 + csr) | ((COUNTER_WIDTH - 1) << 12),
+        Counter::Firmware(_) => 1 << (usize::BITS - 1),
+    })
+}
+
+fn config_matching(base: usize, mask: usize, flags: usize, event: usize, data: usize) -> SbiRet {
+    let Some(mask) = decode_mask(mask, base) else {
+        return SbiRet::invalid_param();
+    };
+    let hart = unsafe { &mut HARTS[hartid()] };
+    let idx = if flags & CFG_FLAG_SKIP_MATCH != 0 {
+        // 沿用调用方之前配置的计数器
+        match each(mask & hart.configured).next() {
+            Some(idx) => idx,
+            None => return SbiRet::invalid_param(),
+        }
+    } else {
+        let candidates = match matching(event, data) {
+            Ok(candidates) => candidates & mask & !hart.configured,
+            Err(err) => return err,
+        };
+        let Some(idx) = each(candidates).next() else {
+            return SbiRet::not_supported();
+        };
+        if let Counter::Hardware(csr) = counter(idx) {
+            configure(csr, event, data, flags);
+        }
+        hart.configured |= 1 << idx;
+        idx
+    };
+    if flags & CFG_FLAG_CLEAR_VALUE != 0 {
+        write(idx, 0);
+    }
+    if flags & CFG_FLAG_AUTO_START != 0 && hart.started >> idx & 1 == 0 {
+        resume(idx);
+        hart.started |= 1 << idx;
+    }
+    SbiRet::success(idx)
+}
+
+/// 可以计数事件的计数器，按逻辑编号排列。
+fn matching(event: usize, data: usize) -> Result<u64, SbiRet> {
+    let code = event & 0xffff;
+    let info = unsafe { &*addr_of!(INFO) };
+    let csrs = match event >> 16 & 0xf {
+        TYPE_FIRMWARE if code < FW_EVENTS => return Ok(1 << (hardware_len() + code)),
+        TYPE_FIRMWARE => return Err(SbiRet::not_supported()),
+        TYPE_HARDWARE => {
+            let fixed = match code {
+                HW_CPU_CYCLES => 1 << 0,
+                HW_INSTRUCTIONS => 1 << 2,
+                _ => 0,
+            };
+            fixed | info.counters_for(event as _)
+        }
+        TYPE_CACHE => info.counters_for(event as _),
+        TYPE_RAW => info.raw_counters_for(data as _),
+        _ => return Err(SbiRet::invalid_param()),
+    };
+    Ok(hardware()
+        .enumerate()
+        .filter(|(_, csr)| csrs >> csr & 1 == 1)
+        .fold(0, |acc, (idx, _)| acc | 1 << idx))
+}
+
+/// 为事件配置硬件计数器，配置后计数器处于停止状态，S 态可以读取。
+fn configure(csr: usize, event: usize, data: usize, flags: usize) {
+    unsafe {
+        asm!("csrs mcountinhibit, {}", in(reg) 1 << csr);
+        asm!("csrs mcounteren, {}", in(reg) 1 << csr);
+    }
+    // mcycle 和 minstret 计数固定的事件
+    if csr < 3 {
+        return;
+    }
+    let mut selector = if event >> 16 & 0xf == TYPE_RAW {
+        data as u64
+    } else {
+        unsafe { (*addr_of!(INFO)).selector_for(event as _) }.unwrap_or(event as _)
+    };
+    if unsafe { SSCOFPMF } {
+        selector |= ((flags & CFG_FLAG_INHIBIT) >> 3 << MHPMEVENT_INHIBIT_SHIFT) as u64;
+    }
+    write_mhpmevent(csr, selector);
+}
+
+fn start(base: usize, mask: usize, flags: usize, initial_value: usize) -> SbiRet {
+    let Some(mask) = decode_mask(mask, base) else {
+        return SbiRet::invalid_param();
+    };
+    let hart = unsafe { &mut HARTS[hartid()] };
+    if mask & !hart.configured != 0 {
+        return SbiRet::invalid_param();
+    }
+    if mask & hart.started != 0 {
+        return SbiRet::already_started();
+    }
+    for idx in each(mask) {
+        if flags & START_FLAG_SET_INIT_VALUE != 0 {
+            write(idx, initial_value as _);
+        }
+        resume(idx);
+    }
+    hart.started |= mask;
+    SbiRet::success(0)
+}
+
+fn stop(base: usize, mask: usize, flags: usize) -> SbiRet {
+    let Some(mask) = decode_mask(mask, base) else {
+        return SbiRet::invalid_param();
+    };
+    let hart = unsafe { &mut HARTS[hartid()] };
+    if mask & !hart.configured != 0 {
+        return SbiRet::invalid_param();
+    }
+    if mask & !hart.started != 0 {
+        return SbiRet::already_stopped();
+    }
+    for idx in each(mask) {
+        if let Counter::Hardware(csr) = counter(idx) {
+            unsafe { asm!("csrs mcountinhibit, {}", in(reg) 1 << csr) };
+            if flags & STOP_FLAG_RESET != 0 && csr >= 3 {
+                write_mhpmevent(csr, 0);
+            }
+        }
+    }
+    hart.started &= !mask;
+    if flags & STOP_FLAG_RESET != 0 {
+        hart.configured &= !mask;
+    }
+    SbiRet::success(0)
+}
+
+fn fw_read(idx: usize) -> SbiRet {
+    if !(hardware_len()..counters()).contains(&idx) {
+        return SbiRet::invalid_param();
+    }
+    let hart = unsafe { &HARTS[hartid()] };
+    SbiRet::success(hart.firmware[idx - hardware_len()] as _)
+}
+
+/// 使计数器开始计数。
+fn resume(idx: usize) {
+    if let Counter::Hardware(csr) = counter(idx) {
+        unsafe { asm!("csrc mcountinhibit, {}", in(reg) 1 << csr) };
+    }
+}
+
+/// 设置计数器的值。
+fn write(idx: usize, value: u64) {
+    match counter(idx) {
+        Counter::Hardware(csr) => for_csr!(csr, |N| unsafe {
+            asm!("csrw {csr}, {0}", in(reg) value, csr = const 0xb00 + N)
+        }),
+        Counter::Firmware(event) => unsafe { HARTS[hartid()].firmware[event] = value },
+    }
+}
+
+fn write_mhpmevent(csr: usize, value: u64) {
+    for_csr!(csr, |N| unsafe {
+        asm!("csrw {csr}, {0}", in(reg) value, csr = const 0x320 + N)
+    })
+}
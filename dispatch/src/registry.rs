@@ -0,0 +1,134 @@
+//! 扩展注册表。
+//!
+//! 每个扩展号的调用次数也记录在这里。
+
+use crate::TrapContext;
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use sbi_spec::binary::{SbiRet, RET_ERR_NOT_SUPPORTED, RET_SUCCESS};
+
+/// 注册表容量，每个旧式调用占一项。
+const CAPACITY: usize = 32;
+
+/// SBI 扩展。
+pub trait Extension: Sync {
+    /// 处理一次调用。
+    ///
+    /// 参数从 `ctx` 的 `a0`-`a5` 读取。调用前 `mepc` 已指向 `ecall` 的下一条指令，
+    /// 不返回原处的调用可以改写它。
+    fn handle(&self, eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet;
+
+    /// `probe_extension` 的返回值，默认为 1。
+    #[inline]
+    fn probe(&self) -> usize {
+        1
+    }
+
+    /// 初始化扩展在硬件线程 `hartid` 上的状态，由每个硬件线程的 [`init`](crate::init) 调用。
+    #[inline]
+    fn init_hart(&self, hartid: usize) {
+        let _ = hartid;
+    }
+
+    /// 是否使用旧式调用的返回约定：只写 `a0`，成功时写值，失败时写错误码。
+    #[inline]
+    fn legacy(&self) -> bool {
+        false
+    }
+}
+
+static mut EXTENSIONS: [Option<(usize, &'static dyn Extension)>; CAPACITY] = [None; CAPACITY];
+
+/// 每一项的调用次数。
+static CALLS: [AtomicUsize; CAPACITY] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicUsize = AtomicUsize::new(0);
+    [ZERO; CAPACITY]
+};
+
+/// 以扩展号 `eid` 注册扩展，重复注册时替换原有的扩展。
+///
+/// 只能在启动时由一个硬件线程调用。
+pub fn register(eid: usize, extension: &'static dyn Extension) {
+    let table = unsafe { &mut *addr_of_mut!(EXTENSIONS) };
+    let slot = match table
+        .iter()
+        .position(|e| matches!(e, Some((id, _)) if *id == eid))
+    {
+        Some(i) => &mut table[i],
+        None => table
+            .iter_mut()
+            .find(|e| e.is_none())
+            .expect("too many extensions"),
+    };
+    *slot = Some((eid, extension));
+}
+
+/// 探测扩展，未注册时返回 0。
+pub fn probe(eid: usize) -> usize {
+    find(eid).map_or(0, |(_, ext)| ext.probe())
+}
+
+/// 扩展号 `eid` 被调用的次数，未注册时返回 `None`。
+pub fn calls(eid: usize) -> Option<usize> {
+    find(eid).map(|(i, _)| CALLS[i].load(Ordering::Relaxed))
+}
+
+fn find(eid: usize) -> Option<(usize, &'static dyn Extension)> {
+    unsafe { &*addr_of!(EXTENSIONS) }
+        .iter()
+        .enumerate()
+        .find_map(|(i, e)| match e {
+            Some((id, ext)) if *id == eid => Some((i, *ext)),
+            _ => None,
+        })
+}
+
+/// 对每个已注册的扩展调用一次 [`Extension::init_hart`]。
+pub(crate) fn init_hart(hartid: usize) {
+    let table = unsafe { &*addr_of!(EXTENSIONS) };
+    for (i, (_, ext)) in table
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| e.map(|e| (i, e)))
+    {
+        // 以多个扩展号注册的扩展只初始化一次
+        let first = table[..i]
+            .iter()
+            .flatten()
+            .all(|(_, other)| !core::ptr::eq(addr(*other), addr(ext)));
+        if first {
+            ext.init_hart(hartid);
+        }
+    }
+}
+
+#[inline]
+fn addr(ext: &dyn Extension) -> *const u8 {
+    ext as *const dyn Extension as _
+}
+
+/// 分发来自 S 态的 `ecall`。
+pub(crate) fn dispatch(ctx: &mut TrapContext) {
+    let (eid, fid) = (ctx.x[17], ctx.x[16]);
+    ctx.mepc += 4;
+    match find(eid) {
+        Some((i, ext)) => {
+            CALLS[i].fetch_add(1, Ordering::Relaxed);
+            let ret = ext.handle(eid, fid, ctx);
+            if ext.legacy() {
+                ctx.x[10] = if ret.error == RET_SUCCESS {
+                    ret.value
+                } else {
+                    ret.error
+                };
+            } else {
+                ctx.x[10] = ret.error;
+                ctx.x[11] = ret.value;
+            }
+        }
+        None => ctx.x[10] = RET_ERR_NOT_SUPPORTED,
+    }
+}
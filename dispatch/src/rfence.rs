@@ -0,0 +1,283 @@
+//! SBI §8 RFENCE 扩展。
+//!
+//! 每个硬件线程有一个信箱，一次容纳一个请求。发送方占用目标的信箱后，
+//! 通过 `ipi` 模块通知目标，目标执行栅栏并清空信箱，发送方等到信箱清空才返回。
+//! 占用和等待期间，发送方持续处理发给自己的请求，以免互相等待。
+//!
+//! 超过 [`FLUSH_THRESHOLD`] 的范围改为全部刷新。
+
+use crate::{hartid, ipi, pmu, register, Extension, TrapContext};
+use core::{arch::asm, hint::spin_loop};
+use machine_info::{IsaExtensions, MachineInfo, MAX_HARTS};
+use sbi_spec::{binary::SbiRet, rfnc::*};
+use spin::Mutex;
+
+/// 逐页刷新的范围上限，更大的范围全部刷新。
+pub(crate) const FLUSH_THRESHOLD: usize = 64 * PAGE_SIZE;
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+/// 每个硬件线程的信箱。
+static MAILBOX: [Mutex<Option<Fence>>; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Mutex<Option<Fence>> = Mutex::new(None);
+    [EMPTY; MAX_HARTS]
+};
+
+/// 所有硬件线程是否都支持虚拟化扩展。
+static mut HYPERVISOR: bool = false;
+
+/// 远程栅栏请求，`size` 为 0 表示全部刷新。
+#[derive(Clone, Copy)]
+enum Fence {
+    I,
+    SfenceVma {
+        start: usize,
+        size: usize,
+    },
+    SfenceVmaAsid {
+        start: usize,
+        size: usize,
+        asid: usize,
+    },
+    HfenceGvma {
+        start: usize,
+        size: usize,
+    },
+    HfenceGvmaVmid {
+        start: usize,
+        size: usize,
+        vmid: usize,
+    },
+    /// `hgatp` 是调用方的值，目标执行时临时换上，以使用调用方当前的 VMID。
+    HfenceVvma {
+        start: usize,
+        size: usize,
+        hgatp: usize,
+    },
+    HfenceVvmaAsid {
+        start: usize,
+        size: usize,
+        asid: usize,
+        hgatp: usize,
+    },
+}
+
+/// RFENCE 扩展。
+pub struct Rfence;
+
+impl Rfence {
+    /// 注册 RFENCE 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        ipi::init(machine);
+        unsafe { HYPERVISOR = machine.isa.contains(IsaExtensions::H) };
+        register(EID_RFNC, self);
+    }
+}
+
+impl Extension for Rfence {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let (start, size) = range(ctx.a(2), ctx.a(3));
+        let fence = match fid {
+            REMOTE_FENCE_I => Fence::I,
+            REMOTE_SFENCE_VMA => Fence::SfenceVma { start, size },
+            REMOTE_SFENCE_VMA_ASID => Fence::SfenceVmaAsid {
+                start,
+                size,
+                asid: ctx.a(4),
+            },
+            _ if !unsafe { HYPERVISOR } => return SbiRet::not_supported(),
+            REMOTE_HFENCE_GVMA => Fence::HfenceGvma { start, size },
+            REMOTE_HFENCE_GVMA_VMID => Fence::HfenceGvmaVmid {
+                start,
+                size,
+                vmid: ctx.a(4),
+            },
+            REMOTE_HFENCE_VVMA => Fence::HfenceVvma {
+                start,
+                size,
+                hgatp: hgatp(),
+            },
+            REMOTE_HFENCE_VVMA_ASID => Fence::HfenceVvmaAsid {
+                start,
+                size,
+                asid: ctx.a(4),
+                hgatp: hgatp(),
+            },
+            _ => return SbiRet::not_supported(),
+        };
+        let mask = match ipi::decode_mask(ctx.a(0), ctx.a(1)) {
+            Ok(mask) => mask,
+            Err(err) => return err,
+        };
+        if !ipi::exists() {
+            return SbiRet::not_supported();
+        }
+        let this = hartid();
+        let targets = (0..MAX_HARTS).filter(|hart| mask >> hart & 1 == 1);
+        for hart in targets.clone() {
+            pmu::record(fence.events().0);
+            if hart == this {
+                fence.perform();
+            } else {
+                post(hart, fence);
+            }
+        }
+        for hart in targets.filter(|hart| *hart != this) {
+            // 信箱清空说明请求已完成，之后可能又有其他硬件线程的请求，多等一会儿也没关系
+            while MAILBOX[hart].lock().is_some() {
+                ipi::poll();
+                spin_loop();
+            }
+        }
+        SbiRet::success(0)
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        ipi::init_hart();
+    }
+}
+
+/// 处理当前硬件线程信箱中的请求。
+pub(crate) fn serve() {
+    let mut slot = MAILBOX[hartid()].lock();
+    if let Some(fence) = slot.take() {
+        fence.perform();
+    }
+}
+
+/// 占用 `hart` 的信箱并通知它。
+fn post(hart: usize, fence: Fence) {
+    loop {
+        if let Some(mut slot) = MAILBOX[hart].try_lock() {
+            if slot.is_none() {
+                *slot = Some(fence);
+                break;
+            }
+        }
+        ipi::poll();
+        spin_loop();
+    }
+    ipi::request(hart, ipi::RFENCE);
+}
+
+/// 规整刷新范围，全部刷新时 `size` 为 0。
+///
+/// 规范规定 `start` 和 `size` 都为 0 或 `size` 为 -1 时全部刷新，超过阈值的范围也全部刷新。
+fn range(start: usize, size: usize) -> (usize, usize) {
+    if size > FLUSH_THRESHOLD {
+        (0, 0)
+    } else {
+        (start, size)
+    }
+}
+
+impl Fence {
+    /// 发出和收到这种栅栏时记录的固件事件。
+    fn events(&self) -> (usize, usize) {
+        use pmu::*;
+        match self {
+            Self::I => (FENCE_I_SENT, FENCE_I_RECEIVED),
+            Self::SfenceVma { .. } => (SFENCE_VMA_SENT, SFENCE_VMA_RECEIVED),
+            Self::SfenceVmaAsid { .. } => (SFENCE_VMA_ASID_SENT, SFENCE_VMA_ASID_RECEIVED),
+            Self::HfenceGvma { .. } => (HFENCE_GVMA_SENT, HFENCE_GVMA_RECEIVED),
+            Self::HfenceGvmaVmid { .. } => (HFENCE_GVMA_VMID_SENT, HFENCE_GVMA_VMID_RECEIVED),
+            Self::HfenceVvma { .. } => (HFENCE_VVMA_SENT, HFENCE_VVMA_RECEIVED),
+            Self::HfenceVvmaAsid { .. } => (HFENCE_VVMA_ASID_SENT, HFENCE_VVMA_ASID_RECEIVED),
+        }
+    }
+
+    /// 在当前硬件线程上执行栅栏。
+    fn perform(self) {
+        pmu::record(self.events().1);
+        match self {
+            Self::I => unsafe { asm!("fence.i") },
+            Self::SfenceVma { start, size } => each_page(start, size, |addr| match addr {
+                Some(addr) => unsafe { asm!("sfence.vma {}", in(reg) addr) },
+                None => unsafe { asm!("sfence.vma") },
+            }),
+            Self::SfenceVmaAsid { start, size, asid } => {
+                each_page(start, size, |addr| match addr {
+                    Some(addr) => unsafe { asm!("sfence.vma {}, {}", in(reg) addr, in(reg) asid) },
+                    None => unsafe { asm!("sfence.vma zero, {}", in(reg) asid) },
+                })
+            }
+            Self::HfenceGvma { start, size } => {
+                each_page(start, size, |addr| hfence_gvma(addr, None))
+            }
+            Self::HfenceGvmaVmid { start, size, vmid } => {
+                each_page(start, size, |addr| hfence_gvma(addr, Some(vmid)))
+            }
+            Self::HfenceVvma { start, size, hgatp } => with_hgatp(hgatp, || {
+                each_page(start, size, |addr| hfence_vvma(addr, None))
+            }),
+            Self::HfenceVvmaAsid {
+                start,
+                size,
+                asid,
+                hgatp,
+            } => with_hgatp(hgatp, || {
+                each_page(start, size, |addr| hfence_vvma(addr, Some(asid)))
+            }),
+        }
+    }
+}
+
+/// 对范围内的每一页执行 `f`，范围为空时以 `None` 执行一次，表示全部刷新。
+#[inline]
+fn each_page(start: usize, size: usize, f: impl Fn(Option<usize>)) {
+    if size == 0 {
+        f(None);
+    } else {
+        let end = start.saturating_add(size);
+        (start & !(PAGE_SIZE - 1)..end)
+            .step_by(PAGE_SIZE)
+            .for_each(|addr| f(Some(addr)));
+    }
+}
+
+// 汇编器未必认识虚拟化扩展的指令，直接编码。
+// 操作数为 `None` 时使用 `zero` 寄存器，表示所有地址或所有 VMID/ASID。
+
+/// `hfence.gvma`，地址是客户物理地址右移 2 位。
+#[inline]
+pub(crate) fn hfence_gvma(gaddr: Option<usize>, vmid: Option<usize>) {
+    unsafe {
+        match (gaddr.map(|a| a >> 2), vmid) {
+            (Some(a), Some(v)) => asm!(".insn r 0x73, 0, 0x31, x0, {}, {}", in(reg) a, in(reg) v),
+            (Some(a), None) => asm!(".insn r 0x73, 0, 0x31, x0, {}, x0", in(reg) a),
+            (None, Some(v)) => asm!(".insn r 0x73, 0, 0x31, x0, x0, {}", in(reg) v),
+            (None, None) => asm!(".insn r 0x73, 0, 0x31, x0, x0, x0"),
+        }
+    }
+}
+
+/// `hfence.vvma`，作用于当前 `hgatp` 中的 VMID。
+#[inline]
+pub(crate) fn hfence_vvma(vaddr: Option<usize>, asid: Option<usize>) {
+    unsafe {
+        match (vaddr, asid) {
+            (Some(a), Some(v)) => asm!(".insn r 0x73, 0, 0x11, x0, {}, {}", in(reg) a, in(reg) v),
+            (Some(a), None) => asm!(".insn r 0x73, 0, 0x11, x0, {}, x0", in(reg) a),
+            (None, Some(v)) => asm!(".insn r 0x73, 0, 0x11, x0, x0, {}", in(reg) v),
+            (None, None) => asm!(".insn r 0x73, 0, 0x11, x0, x0, x0"),
+        }
+    }
+}
+
+#[inline]
+pub(crate) fn hgatp() -> usize {
+    let ans: usize;
+    unsafe { asm!("csrr {}, 0x680", out(reg) ans) };
+    ans
+}
+
+/// 临时换上 `hgatp` 执行 `f`。
+#[inline]
+pub(crate) fn with_hgatp(hgatp: usize, f: impl FnOnce()) {
+    let saved: usize;
+    unsafe { asm!("csrrw {}, 0x680, {}", out(reg) saved, in(reg) hgatp) };
+    f();
+    unsafe { asm!("csrw 0x680, {}", in(reg) saved) };
+}
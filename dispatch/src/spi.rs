@@ -0,0 +1,45 @@
+//! SBI §7 IPI 扩展。
+
+use crate::{ipi, register, Extension, TrapContext};
+use machine_info::MachineInfo;
+use sbi_spec::{binary::SbiRet, spi::*};
+
+/// IPI 扩展。
+pub struct Ipi;
+
+impl Ipi {
+    /// 注册 IPI 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        ipi::init(machine);
+        register(EID_SPI, self);
+    }
+}
+
+impl Extension for Ipi {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            SEND_IPI => send_ipi(ctx.a(0), ctx.a(1)),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        ipi::init_hart();
+    }
+}
+
+fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> SbiRet {
+    let mask = match ipi::decode_mask(hart_mask, hart_mask_base) {
+        Ok(mask) => mask,
+        Err(err) => return err,
+    };
+    if !ipi::exists() {
+        return SbiRet::not_supported();
+    }
+    (0..usize::BITS as usize)
+        .filter(|hart| mask >> hart & 1 == 1)
+        .for_each(ipi::send_supervisor);
+    SbiRet::success(0)
+}
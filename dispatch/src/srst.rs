@@ -0,0 +1,89 @@
+//! SBI §10 SRST 扩展。
+//!
+//! 复位通过 SiFive 测试设备完成：无原因的关机写 `pass`，系统故障导致的关机写 `fail`，
+//! 冷重启和热重启都写 `reset`。没有测试设备时返回 `NOT_SUPPORTED`。
+//!
+//! 复位之前，其他硬件线程通过 `ipi` 模块停在机器态。
+
+use crate::{hartid, ipi, register, Extension, TrapContext};
+use machine_info::MachineInfo;
+use sbi_spec::{binary::SbiRet, srst::*};
+use sifive_test_device::SifiveTestDevice;
+
+/// 系统故障导致关机时，测试设备报告的退出码。
+const FAILURE_CODE: u16 = 1;
+
+/// 厂商定义的复位类型和原因从这里开始。
+const VENDOR: u32 = 0xf000_0000;
+/// SBI 实现定义的复位原因从这里开始。
+const IMPLEMENTATION: u32 = 0xe000_0000;
+
+/// 测试设备地址，没有时为 0。
+static mut TEST: usize = 0;
+
+/// SRST 扩展。
+pub struct Srst;
+
+impl Srst {
+    /// 注册 SRST 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        ipi::init(machine);
+        unsafe { TEST = machine.test.start };
+        register(EID_SRST, self);
+    }
+}
+
+impl Extension for Srst {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            SYSTEM_RESET => system_reset(ctx.a(0) as u32, ctx.a(1) as u32),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        ipi::init_hart();
+    }
+}
+
+fn system_reset(reset_type: u32, reset_reason: u32) -> SbiRet {
+    match reset_type {
+        RESET_TYPE_SHUTDOWN | RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT => {}
+        VENDOR.. => return SbiRet::not_supported(),
+        _ => return SbiRet::invalid_param(),
+    }
+    let failure = match reset_reason {
+        RESET_REASON_NO_REASON => false,
+        RESET_REASON_SYSTEM_FAILURE | IMPLEMENTATION.. => true,
+        _ => return SbiRet::invalid_param(),
+    };
+    let test = unsafe { TEST };
+    if test == 0 {
+        return SbiRet::not_supported();
+    }
+    halt_others();
+    let test = unsafe { &*(test as *const SifiveTestDevice) };
+    match reset_type {
+        RESET_TYPE_SHUTDOWN if failure => test.fail(FAILURE_CODE),
+        RESET_TYPE_SHUTDOWN => test.pass(),
+        _ => test.reset(),
+    }
+}
+
+/// 使其他硬件线程停在机器态，等待它们都收到请求。
+///
+/// 只请求已进入固件的硬件线程，停在引导代码里的硬件线程不处理请求，等待它们会卡住。
+fn halt_others() {
+    if !ipi::exists() {
+        return;
+    }
+    let others = ipi::available() & !(1 << hartid());
+    if others == 0 {
+        return;
+    }
+    let harts = || (0..usize::BITS as usize).filter(move |hart| others >> hart & 1 == 1);
+    harts().for_each(|hart| ipi::request(hart, ipi::HALT));
+    harts().for_each(|hart| ipi::wait(hart, ipi::HALT));
+}
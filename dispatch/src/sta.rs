@@ -0,0 +1,139 @@
+//! SBI §16 STA 扩展。
+//!
+//! S 态为每个硬件线程指定一块 64 字节的共享内存，布局为：
+//!
+//! | 偏移 | 类型 | 内容
+//! |:----:|:----:|-
+//! | 0    | u32  | 序号，更新期间为奇数
+//! | 4    | u32  | 标志，目前为 0
+//! | 8    | u64  | 被占用的时间，单位纳秒
+//! | 16   | u8   | 是否被抢占
+//!
+//! 固件在处理陷入时用 `mtime` 计时，离开时把这段时间累加到被占用的时间里。
+
+use crate::{clint, hartid, memory, register, Extension, TrapContext};
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{fence, Ordering},
+};
+use machine_info::{MachineInfo, MAX_HARTS};
+use sbi_spec::binary::SbiRet;
+
+/// STA 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_STA: usize = 0x535441;
+const STEAL_TIME_SET_SHMEM: usize = 0;
+
+/// 共享内存的大小和对齐。
+const SHMEM_SIZE: usize = 64;
+/// 共享内存地址的低位和高位都是这个值时，停止更新。
+const SHMEM_DISABLE: usize = usize::MAX;
+
+/// 共享内存的布局。
+#[repr(C)]
+struct StealTime {
+    sequence: u32,
+    flags: u32,
+    steal: u64,
+    preempted: u8,
+    pad: [u8; 47],
+}
+
+/// 每个硬件线程的共享内存地址，0 表示没有设置。
+static mut SHMEM: [usize; MAX_HARTS] = [0; MAX_HARTS];
+/// 每个硬件线程累计被占用的 `mtime` 周期数。
+static mut STOLEN: [u64; MAX_HARTS] = [0; MAX_HARTS];
+/// `mtime` 的频率。
+static mut FREQUENCY: u64 = 0;
+
+/// STA 扩展。
+pub struct Sta;
+
+impl Sta {
+    /// 注册 STA 扩展。没有 CLINT 或不知道 `mtime` 的频率时无法计时，不注册。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        if machine.clint.is_empty() || machine.timebase_frequency == 0 {
+            return;
+        }
+        clint::init(&machine.clint);
+        unsafe { FREQUENCY = machine.timebase_frequency as _ };
+        register(EID_STA, self);
+    }
+}
+
+impl Extension for Sta {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            STEAL_TIME_SET_SHMEM => set_shmem(ctx.a(0), ctx.a(1), ctx.a(2)),
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
+
+fn set_shmem(lo: usize, hi: usize, flags: usize) -> SbiRet {
+    if flags != 0 {
+        return SbiRet::invalid_param();
+    }
+    let hartid = hartid();
+    if lo == SHMEM_DISABLE && hi == SHMEM_DISABLE {
+        unsafe { SHMEM[hartid] = 0 };
+        return SbiRet::success(0);
+    }
+    if lo % SHMEM_SIZE != 0 {
+        return SbiRet::invalid_param();
+    }
+    let memory = memory::range();
+    if hi != 0 || lo < memory.start || lo.saturating_add(SHMEM_SIZE) > memory.end {
+        return SbiRet::invalid_address();
+    }
+    unsafe {
+        STOLEN[hartid] = 0;
+        (lo as *mut StealTime).write_volatile(StealTime {
+            sequence: 0,
+            flags: 0,
+            steal: 0,
+            preempted: 0,
+            pad: [0; 47],
+        });
+        SHMEM[hartid] = lo;
+    }
+    SbiRet::success(0)
+}
+
+/// 进入陷入处理时调用，当前硬件线程设置了共享内存时返回开始的时刻。
+#[inline]
+pub(crate) fn enter() -> Option<u64> {
+    if unsafe { SHMEM[hartid()] } != 0 {
+        Some(clint::mtime())
+    } else {
+        None
+    }
+}
+
+/// 离开陷入处理时调用，把这次陷入占用的时间写入共享内存。
+pub(crate) fn leave(start: Option<u64>) {
+    let Some(start) = start else {
+        return;
+    };
+    let hartid = hartid();
+    let shmem = unsafe { SHMEM[hartid] };
+    // 这次陷入可能关闭了更新
+    if shmem == 0 {
+        return;
+    }
+    let stolen = unsafe {
+        STOLEN[hartid] += clint::mtime().wrapping_sub(start);
+        STOLEN[hartid]
+    };
+    let nanos = (stolen as u128 * 1_000_000_000 / unsafe { FREQUENCY } as u128) as u64;
+    let shmem = shmem as *mut StealTime;
+    unsafe {
+        let sequence = addr_of_mut!((*shmem).sequence);
+        sequence.write_volatile(sequence.read_volatile().wrapping_add(1));
+        fence(Ordering::Release);
+        addr_of_mut!((*shmem).steal).write_volatile(nanos);
+        fence(Ordering::Release);
+        sequence.write_volatile(sequence.read_volatile().wrapping_add(1));
+    }
+}
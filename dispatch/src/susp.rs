@@ -0,0 +1,56 @@
+//! SBI §13 SUSP 扩展。
+//!
+//! 系统挂起到内存时只有调用方在运行，其他硬件线程都处于 `STOPPED`。
+//! 挂起和恢复复用 HSM 的非保持挂起：`wfi` 等待唤醒，经热启动入口重新初始化机器态，
+//! 再以 S 态进入 `resume_addr`。
+
+use crate::{denied, hsm, register, Extension, Hsm, TrapContext};
+use sbi_spec::binary::SbiRet;
+
+/// SUSP 扩展号，`sbi-spec` 0.0.4 还没有这个扩展。
+const EID_SUSP: usize = 0x53555350;
+const SYSTEM_SUSPEND: usize = 0;
+/// 挂起到内存。
+const SUSPEND_TO_RAM: u32 = 0;
+
+/// SUSP 扩展。
+pub struct Susp {
+    hsm: &'static Hsm,
+}
+
+impl Susp {
+    /// 创建 SUSP 扩展，通过 `hsm` 挂起和恢复。
+    #[inline]
+    pub const fn new(hsm: &'static Hsm) -> Self {
+        Self { hsm }
+    }
+
+    /// 注册 SUSP 扩展，`hsm` 必须已经初始化。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self) {
+        register(EID_SUSP, self);
+    }
+}
+
+impl Extension for Susp {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            SYSTEM_SUSPEND => self.system_suspend(ctx),
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
+
+impl Susp {
+    fn system_suspend(&self, ctx: &TrapContext) -> SbiRet {
+        let (sleep_type, resume_addr, opaque) = (ctx.a(0) as u32, ctx.a(1), ctx.a(2));
+        if sleep_type != SUSPEND_TO_RAM {
+            return SbiRet::invalid_param();
+        }
+        if !hsm::others_stopped() {
+            return denied();
+        }
+        self.hsm.suspend(ctx, resume_addr, opaque)
+    }
+}
@@ -0,0 +1,87 @@
+//! SBI §6 TIME 扩展。
+//!
+//! 没有 Sstc 时，`set_timer` 设置 CLINT 中当前硬件线程的 `mtimecmp` 并清除 `mip.STIP`，
+//! 机器态定时器中断到来时关闭它并置位 `mip.STIP`，转发给 S 态。
+//!
+//! 有 Sstc 时，打开 `menvcfg.STCE`，S 态可以直接写 `stimecmp`，
+//! `set_timer` 也改为写 `stimecmp`，此时 `mip.STIP` 由硬件根据它产生。
+
+use crate::{clint, hartid, pmu, register, register_interrupt, Extension, TrapContext};
+use core::arch::asm;
+use machine_info::{IsaExtensions, MachineInfo};
+use sbi_spec::{binary::SbiRet, time::*};
+
+const MIP_STIP: usize = 1 << 5;
+const MIE_MTIE: usize = 1 << 7;
+const MENVCFG_STCE: usize = 1 << 63;
+
+/// 所有硬件线程是否都支持 Sstc。
+static mut SSTC: bool = false;
+
+/// TIME 扩展。
+pub struct Time;
+
+impl Time {
+    /// 注册 TIME 扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self, machine: &MachineInfo) {
+        init(machine);
+        register(EID_TIME, self);
+    }
+}
+
+impl Extension for Time {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        match fid {
+            SET_TIMER => set_timer(ctx.a(0) as _),
+            _ => SbiRet::not_supported(),
+        }
+    }
+
+    fn init_hart(&self, _hartid: usize) {
+        init_hart();
+    }
+}
+
+/// 记录定时器来源并注册机器态定时器中断。TIME 扩展和旧式调用共用。
+pub(crate) fn init(machine: &MachineInfo) {
+    clint::init(&machine.clint);
+    unsafe { SSTC = machine.isa.contains(IsaExtensions::SSTC) };
+    register_interrupt(crate::MACHINE_TIMER, on_timer);
+}
+
+/// 有 Sstc 时，允许当前硬件线程的 S 态使用 `stimecmp`。
+pub(crate) fn init_hart() {
+    if unsafe { SSTC } {
+        // menvcfg
+        unsafe { asm!("csrs 0x30a, {}", in(reg) MENVCFG_STCE) };
+    }
+}
+
+/// 设置当前硬件线程的下一次定时器中断。
+pub(crate) fn set_timer(time: u64) -> SbiRet {
+    pmu::record(pmu::SET_TIMER);
+    if unsafe { SSTC } {
+        // stimecmp
+        unsafe { asm!("csrw 0x14d, {}", in(reg) time) };
+        return SbiRet::success(0);
+    }
+    if !clint::exists() {
+        return SbiRet::not_supported();
+    }
+    clint::set_timecmp(hartid(), time);
+    unsafe {
+        asm!("csrc mip, {}", in(reg) MIP_STIP);
+        asm!("csrs mie, {}", in(reg) MIE_MTIE);
+    }
+    SbiRet::success(0)
+}
+
+/// 机器态定时器中断：关闭定时器中断，转发给 S 态。
+fn on_timer() {
+    unsafe {
+        asm!("csrc mie, {}", in(reg) MIE_MTIE);
+        asm!("csrs mip, {}", in(reg) MIP_STIP);
+    }
+}
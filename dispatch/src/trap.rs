@@ -0,0 +1,328 @@
+//! 机器态陷入处理。
+//!
+//! 陷入只会来自低特权级，`mscratch` 平时保存机器态栈顶，由 `linker::boot0!` 设为启动栈栈顶。
+//! 入口与 `sp` 交换后在机器态栈上保存低特权级的上下文。
+
+use crate::{pmu, registry, sta};
+use core::{arch::asm, ptr::addr_of};
+
+/// 机器态软件中断号。
+pub const MACHINE_SOFT: usize = 3;
+/// 机器态定时器中断号。
+pub const MACHINE_TIMER: usize = 7;
+/// 机器态外部中断号。
+pub const MACHINE_EXTERNAL: usize = 11;
+
+const INTERRUPT: usize = 1 << (usize::BITS - 1);
+const ILLEGAL_INSTRUCTION: usize = 2;
+const LOAD_MISALIGNED: usize = 4;
+const LOAD_ACCESS: usize = 5;
+const STORE_MISALIGNED: usize = 6;
+const STORE_ACCESS: usize = 7;
+const SUPERVISOR_ECALL: usize = 9;
+
+const MSTATUS_SIE: usize = 1 << 1;
+const MSTATUS_SPIE: usize = 1 << 5;
+const MSTATUS_SPP: usize = 1 << 8;
+const MSTATUS_MPP: usize = 0b11 << 11;
+const MSTATUS_MPRV: usize = 1 << 17;
+const MPP_SUPERVISOR: usize = 0b01 << 11;
+
+/// 陷入入口在机器态栈上分配的空间。
+const FRAME_SIZE: usize = 34 * 8;
+
+/// 陷入时保存的低特权级上下文。
+#[repr(C)]
+pub struct TrapContext {
+    /// 通用寄存器，`x[0]` 不保存。
+    pub x: [usize; 32],
+    /// 陷入时的 `mepc`。
+    pub mepc: usize,
+}
+
+impl TrapContext {
+    /// 第 `i` 个参数寄存器 `a<i>`。
+    #[inline]
+    pub fn a(&self, i: usize) -> usize {
+        self.x[10 + i]
+    }
+}
+
+/// 各机器态中断的处理函数。
+static mut INTERRUPTS: [Option<fn()>; 16] = [None; 16];
+
+/// 注册机器态中断 `code` 的处理函数。
+///
+/// 只能在启动时由一个硬件线程调用。
+pub fn register_interrupt(code: usize, handler: fn()) {
+    unsafe { INTERRUPTS[code] = Some(handler) };
+}
+
+/// 设置当前硬件线程的陷入入口，并初始化已注册的扩展在这个硬件线程上的状态。
+///
+/// 启动栈此后只在陷入时使用。每个硬件线程都要在注册完扩展之后、离开机器态之前调用。
+pub fn init() {
+    unsafe {
+        asm!(
+            "la   {0}, {entry}",
+            "csrw mtvec, {0}",
+            out(reg) _,
+            entry = sym trap_entry,
+        )
+    };
+    registry::init_hart(crate::hartid());
+}
+
+extern "C" fn trap_handler(ctx: &mut TrapContext) {
+    // 处理陷入的时间计入 S 态被占用的时间
+    let start = sta::enter();
+    handle(ctx);
+    sta::leave(start);
+}
+
+fn handle(ctx: &mut TrapContext) {
+    let mcause: usize;
+    unsafe { asm!("csrr {}, mcause", out(reg) mcause) };
+    let handler = match mcause {
+        SUPERVISOR_ECALL => return registry::dispatch(ctx),
+        // 没有委托的异常计数后转交 S 态
+        ILLEGAL_INSTRUCTION | LOAD_MISALIGNED..=STORE_ACCESS => {
+            pmu::record(match mcause {
+                ILLEGAL_INSTRUCTION => pmu::ILLEGAL_INSN,
+                LOAD_MISALIGNED => pmu::MISALIGNED_LOAD,
+                LOAD_ACCESS => pmu::ACCESS_LOAD,
+                STORE_MISALIGNED => pmu::MISALIGNED_STORE,
+                _ => pmu::ACCESS_STORE,
+            });
+            return redirect(ctx, mcause);
+        }
+        _ if mcause & INTERRUPT != 0 => {
+            unsafe { (*addr_of!(INTERRUPTS)).get(mcause & !INTERRUPT) }.copied()
+        }
+        _ => None,
+    };
+    match handler.flatten() {
+        Some(handler) => handler(),
+        None => panic!(
+            "unsupported trap: mcause = {mcause:#x}, mepc = {:#x}",
+            ctx.mepc
+        ),
+    }
+}
+
+/// 把来自低特权级的异常转交 S 态，如同它被委托给 S 态。
+fn redirect(ctx: &mut TrapContext, mcause: usize) {
+    let mstatus: usize;
+    let mtval: usize;
+    let stvec: usize;
+    unsafe {
+        asm!("csrr {}, mstatus", out(reg) mstatus);
+        asm!("csrr {}, mtval", out(reg) mtval);
+        asm!("csrr {}, stvec", out(reg) stvec);
+    }
+    assert_ne!(
+        mstatus & MSTATUS_MPP,
+        MSTATUS_MPP,
+        "exception in machine mode: mcause = {mcause:#x}, mepc = {:#x}",
+        ctx.mepc
+    );
+    let mut status = mstatus & !(MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_MPP);
+    if mstatus & MSTATUS_SIE != 0 {
+        status |= MSTATUS_SPIE;
+    }
+    if mstatus & MSTATUS_MPP == MPP_SUPERVISOR {
+        status |= MSTATUS_SPP;
+    }
+    status |= MPP_SUPERVISOR;
+    unsafe {
+        asm!("csrw scause, {}", in(reg) mcause);
+        asm!("csrw stval, {}", in(reg) mtval);
+        asm!("csrw sepc, {}", in(reg) ctx.mepc);
+        asm!("csrw mstatus, {}", in(reg) status);
+    }
+    // 向量模式下异常也进入基地址
+    ctx.mepc = stvec & !0b11;
+}
+
+/// 不经过陷入出口离开机器态之前，把 `mscratch` 恢复为机器态栈顶。
+///
+/// `ctx` 必须是当前陷入保存的上下文。
+pub(crate) fn restore_scratch(ctx: &TrapContext) {
+    let top = ctx as *const _ as usize + FRAME_SIZE;
+    unsafe { asm!("csrw mscratch, {}", in(reg) top) };
+}
+
+/// 设置委托、计数器权限和物理内存保护，然后以 S 态进入 `entry`。
+///
+/// S 态收到的 `a0` 是硬件线程号，`a1` 是 `opaque`，`satp` 为 0，`sstatus.SIE` 关闭。
+///
+/// # Safety
+///
+/// `entry` 必须是 S 态软件的入口。
+pub unsafe fn enter_supervisor(hartid: usize, opaque: usize, entry: usize) -> ! {
+    // 指令地址不对齐、断点、用户态系统调用和页异常交给 S 态
+    const MEDELEG: usize = 1 << 0 | 1 << 3 | 1 << 8 | 1 << 12 | 1 << 13 | 1 << 15;
+    // S 态的软件、定时器和外部中断
+    const MIDELEG: usize = 1 << 1 | 1 << 5 | 1 << 9;
+    // cycle、time 和 instret
+    const MCOUNTEREN: usize = 0b111;
+    // 覆盖整个地址空间的 NAPOT 区域，可读可写可执行
+    const PMPCFG0: usize = 0b11 << 3 | 0b111;
+    asm!(
+        "csrw medeleg,    {medeleg}",
+        "csrw mideleg,    {mideleg}",
+        "csrw mcounteren, {mcounteren}",
+        "csrw pmpaddr0,   {pmpaddr0}",
+        "csrw pmpcfg0,    {pmpcfg0}",
+        // 重新启动的硬件线程可能留有上次的 S 态状态
+        "csrw satp,       zero",
+        "csrc sstatus,    {sie}",
+        "csrc mstatus,    {mpp}",
+        "csrs mstatus,    {supervisor}",
+        "csrw mepc,       {entry}",
+        "mret",
+        medeleg = in(reg) MEDELEG,
+        mideleg = in(reg) MIDELEG,
+        mcounteren = in(reg) MCOUNTEREN,
+        pmpaddr0 = in(reg) usize::MAX,
+        pmpcfg0 = in(reg) PMPCFG0,
+        sie = in(reg) MSTATUS_SIE,
+        mpp = in(reg) MSTATUS_MPP,
+        supervisor = in(reg) MPP_SUPERVISOR,
+        entry = in(reg) entry,
+        in("a0") hartid,
+        in("a1") opaque,
+        options(noreturn),
+    )
+}
+
+/// 以 S 态的地址转换和访问权限读取 `addr` 处的一个字。
+///
+/// 只能在处理来自 S 态的陷入时调用，此时 `mstatus.MPP` 为 S。
+/// 访问失败时不会陷入 S 态，而是返回失败原因 `mcause`。
+pub fn read_supervisor(addr: usize) -> Result<usize, usize> {
+    let value: usize;
+    let cause: usize;
+    unsafe {
+        asm!(
+            "csrr {mepc}, mepc",
+            "csrr {mstatus}, mstatus",
+            "csrr {mtvec}, mtvec",
+            "la   {cause}, 1f",
+            "csrw mtvec, {cause}",
+            "csrs mstatus, {mprv}",
+            "li   {cause}, 0",
+            "li   {value}, 0",
+            "ld   {value}, 0({addr})",
+            "j    2f",
+            // 访问失败时跳到这里，陷入时 MIE 已关闭，不会被中断打断
+            ".align 2",
+            "1:",
+            "csrr {cause}, mcause",
+            "2:",
+            "csrw mstatus, {mstatus}",
+            "csrw mtvec, {mtvec}",
+            "csrw mepc, {mepc}",
+            addr = in(reg) addr,
+            mprv = in(reg) MSTATUS_MPRV,
+            value = out(reg) value,
+            cause = out(reg) cause,
+            mepc = out(reg) _,
+            mstatus = out(reg) _,
+            mtvec = out(reg) _,
+        )
+    };
+    if cause == 0 {
+        Ok(value)
+    } else {
+        Err(cause)
+    }
+}
+
+/// 陷入入口，`mtvec` 直接模式要求 4 字节对齐。
+#[naked]
+#[repr(align(4))]
+unsafe extern "C" fn trap_entry() -> ! {
+    asm!(
+        // sp <- 机器态栈，mscratch <- 低特权级 sp
+        "csrrw sp, mscratch, sp",
+        "addi  sp, sp, -{frame}",
+        "sd    x1,   1*8(sp)",
+        "sd    x3,   3*8(sp)",
+        "sd    x4,   4*8(sp)",
+        "sd    x5,   5*8(sp)",
+        "sd    x6,   6*8(sp)",
+        "sd    x7,   7*8(sp)",
+        "sd    x8,   8*8(sp)",
+        "sd    x9,   9*8(sp)",
+        "sd   x10,  10*8(sp)",
+        "sd   x11,  11*8(sp)",
+        "sd   x12,  12*8(sp)",
+        "sd   x13,  13*8(sp)",
+        "sd   x14,  14*8(sp)",
+        "sd   x15,  15*8(sp)",
+        "sd   x16,  16*8(sp)",
+        "sd   x17,  17*8(sp)",
+        "sd   x18,  18*8(sp)",
+        "sd   x19,  19*8(sp)",
+        "sd   x20,  20*8(sp)",
+        "sd   x21,  21*8(sp)",
+        "sd   x22,  22*8(sp)",
+        "sd   x23,  23*8(sp)",
+        "sd   x24,  24*8(sp)",
+        "sd   x25,  25*8(sp)",
+        "sd   x26,  26*8(sp)",
+        "sd   x27,  27*8(sp)",
+        "sd   x28,  28*8(sp)",
+        "sd   x29,  29*8(sp)",
+        "sd   x30,  30*8(sp)",
+        "sd   x31,  31*8(sp)",
+        "csrr  t0, mscratch",
+        "sd    t0,   2*8(sp)",
+        "csrr  t0, mepc",
+        "sd    t0,  32*8(sp)",
+        "mv    a0, sp",
+        "call  {handler}",
+        "ld    t0,  32*8(sp)",
+        "csrw  mepc, t0",
+        // 低特权级 sp 暂存在 mscratch，出口再交换回来
+        "ld    t0,   2*8(sp)",
+        "csrw  mscratch, t0",
+        "ld    x1,   1*8(sp)",
+        "ld    x3,   3*8(sp)",
+        "ld    x4,   4*8(sp)",
+        "ld    x5,   5*8(sp)",
+        "ld    x6,   6*8(sp)",
+        "ld    x7,   7*8(sp)",
+        "ld    x8,   8*8(sp)",
+        "ld    x9,   9*8(sp)",
+        "ld   x10,  10*8(sp)",
+        "ld   x11,  11*8(sp)",
+        "ld   x12,  12*8(sp)",
+        "ld   x13,  13*8(sp)",
+        "ld   x14,  14*8(sp)",
+        "ld   x15,  15*8(sp)",
+        "ld   x16,  16*8(sp)",
+        "ld   x17,  17*8(sp)",
+        "ld   x18,  18*8(sp)",
+        "ld   x19,  19*8(sp)",
+        "ld   x20,  20*8(sp)",
+        "ld   x21,  21*8(sp)",
+        "ld   x22,  22*8(sp)",
+        "ld   x23,  23*8(sp)",
+        "ld   x24,  24*8(sp)",
+        "ld   x25,  25*8(sp)",
+        "ld   x26,  26*8(sp)",
+        "ld   x27,  27*8(sp)",
+        "ld   x28,  28*8(sp)",
+        "ld   x29,  29*8(sp)",
+        "ld   x30,  30*8(sp)",
+        "ld   x31,  31*8(sp)",
+        "addi  sp, sp, {frame}",
+        "csrrw sp, mscratch, sp",
+        "mret",
+        frame = const FRAME_SIZE,
+        handler = sym trap_handler,
+        options(noreturn),
+    )
+}
@@ -0,0 +1,132 @@
+//! 本教程自己的固件扩展，向 S 态报告固件内部的状态，便于在运行的内核中调试固件。
+//!
+//! 缓冲区的约定与 DBCN 相同：物理地址分为低位和高位，必须完全位于内存中，否则返回 `INVALID_PARAM`。
+//! 返回值是写入的字节数，缓冲区不够时截断。
+//!
+//! | 功能号 | 参数 | 内容
+//! |:------:|:----:|-
+//! | 0 | 缓冲区 | 构建信息文本：章节、git 提交和启用的特性，以空格分隔
+//! | 1 | -      | 仍然保留的固件日志的起始位置
+//! | 2 | 位置、缓冲区 | 从位置开始读取固件日志，位置已被覆盖或超出时返回 `INVALID_PARAM`
+//! | 3 | 扩展号 | 扩展号被调用的次数，未注册时返回 `INVALID_PARAM`
+//! | 4 | 缓冲区 | 内存布局：.text、.rodata、.data、.bss 和启动栈的起止地址，每个地址 8 字节
+
+use crate::{base::IMPL_ID, calls, dbcn::buffer, memory, register, Extension, TrapContext};
+use core::fmt::{self, Write};
+use sbi_spec::binary::SbiRet;
+
+/// 固件扩展号段是 0x0a000000-0x0affffff，低位取实现号。
+const EID_VENDOR: usize = 0x0a00_0000 + IMPL_ID;
+const BUILD_INFO: usize = 0;
+const LOG_START: usize = 1;
+const LOG_READ: usize = 2;
+const CALL_COUNT: usize = 3;
+const MEMORY_LAYOUT: usize = 4;
+
+/// 构建信息，由各章在编译时给出。
+pub struct BuildInfo {
+    /// 章节，通常是 `env!("CARGO_PKG_NAME")`。
+    pub chapter: &'static str,
+    /// git 提交。
+    pub git: &'static str,
+    /// 启用的特性。
+    pub features: &'static [&'static str],
+}
+
+/// 固件扩展。
+pub struct Vendor {
+    build: BuildInfo,
+}
+
+impl Vendor {
+    /// 以构建信息 `build` 创建固件扩展。
+    #[inline]
+    pub const fn new(build: BuildInfo) -> Self {
+        Self { build }
+    }
+
+    /// 注册固件扩展。
+    ///
+    /// 只能在启动时由一个硬件线程调用。
+    pub fn init(&'static self) {
+        register(EID_VENDOR, self);
+    }
+}
+
+impl Extension for Vendor {
+    fn handle(&self, _eid: usize, fid: usize, ctx: &mut TrapContext) -> SbiRet {
+        let memory = &memory::range();
+        match fid {
+            BUILD_INFO => match buffer(memory, ctx.a(0), ctx.a(1), ctx.a(2)) {
+                Some(buf) => {
+                    let mut cursor = Cursor { buf, len: 0 };
+                    let _ = self.write_build_info(&mut cursor);
+                    SbiRet::success(cursor.len)
+                }
+                None => SbiRet::invalid_param(),
+            },
+            LOG_START => SbiRet::success(console::history::range().start),
+            LOG_READ => match buffer(memory, ctx.a(1), ctx.a(2), ctx.a(3)) {
+                Some(buf) => match console::history::read(ctx.a(0), buf) {
+                    Some(n) => SbiRet::success(n),
+                    None => SbiRet::invalid_param(),
+                },
+                None => SbiRet::invalid_param(),
+            },
+            CALL_COUNT => match calls(ctx.a(0)) {
+                Some(n) => SbiRet::success(n),
+                None => SbiRet::invalid_param(),
+            },
+            MEMORY_LAYOUT => match buffer(memory, ctx.a(0), ctx.a(1), ctx.a(2)) {
+                Some(buf) => {
+                    let layout = linker::layout();
+                    let sections = [
+                        layout.text,
+                        layout.rodata,
+                        layout.data,
+                        layout.bss,
+                        layout.boot,
+                    ];
+                    let words = sections.iter().flat_map(|s| [s.start, s.end]);
+                    let mut len = 0;
+                    for (chunk, word) in buf.chunks_exact_mut(8).zip(words) {
+                        chunk.copy_from_slice(&(word as u64).to_le_bytes());
+                        len += 8;
+                    }
+                    SbiRet::success(len)
+                }
+                None => SbiRet::invalid_param(),
+            },
+            _ => SbiRet::not_supported(),
+        }
+    }
+}
+
+impl Vendor {
+    fn write_build_info(&self, w: &mut impl Write) -> fmt::Result {
+        write!(w, "{} {}", self.build.chapter, self.build.git)?;
+        for feature in self.build.features {
+            write!(w, " {feature}")?;
+        }
+        Ok(())
+    }
+}
+
+/// 写入缓冲区，写满后截断。
+struct Cursor {
+    buf: &'static mut [u8],
+    len: usize,
+}
+
+impl Write for Cursor {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..][..n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        if n == s.len() {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
@@ -1,8 +1,10 @@
-//! 这个项目用于复用链接脚本，并提供依赖内存布局和符号的操作，包括设置启动栈和清零 .bss 节。
+//! 这个项目用于复用链接脚本，并提供依赖内存布局和符号的操作，包括设置启动栈、热启动入口、清零 .bss 节和查询各节的范围。
 
 #![no_std]
 #![deny(warnings, missing_docs)]
 
+use core::ops::Range;
+
 /// 链接脚本文本。
 pub const SCRIPT: &[u8] = b"
 OUTPUT_ARCH(riscv)
@@ -10,14 +12,17 @@ ENTRY(_start)
 MEMORY { DRAM : ORIGIN = 0x80000000, LENGTH = 2M }
 SECTIONS {
     .text : {
+        __text = .;
         *(.text.entry)
         *(.text .text.*)
     } > DRAM
     .rodata : {
+        __rodata = .;
         *(.rodata .rodata.*)
         *(.srodata .srodata.*)
     } > DRAM
     .data : {
+        __data = .;
         *(.data .data.*)
         *(.sdata .sdata.*)
     } > DRAM
@@ -40,20 +45,61 @@ SECTIONS {
 
 /// 定义内核入口。
 ///
-/// 将设置一个启动栈，并在启动栈上调用高级语言入口。
+/// 将为每个硬件线程设置一个启动栈，并在启动栈上调用高级语言入口。
+/// 栈顶同时写入 `mscratch`，离开机器态后作为陷入处理的栈。
+///
+/// 可以用 `harts` 指定支持的硬件线程数，默认为 1。硬件线程号超出的硬件线程停在 `wfi`。
 #[macro_export]
 macro_rules! boot0 {
     ($entry:ident; stack = $stack:expr) => {
+        $crate::boot0!($entry; stack = $stack; harts = 1);
+    };
+    ($entry:ident; stack = $stack:expr; harts = $harts:expr) => {
         #[link_section = ".text.entry"]
         #[no_mangle]
         #[naked]
         unsafe extern "C" fn _start() -> ! {
             #[link_section = ".boot.stack"]
-            static mut STACK: [u8; $stack] = [0u8; $stack];
+            static mut STACK: [u8; $stack * $harts] = [0u8; $stack * $harts];
 
             core::arch::asm!(
-                "la sp, __end",
-                "j  {main}",
+                "   csrr t0, mhartid
+                    li   t1, {harts}
+                    bgeu t0, t1, 1f
+                    addi t0, t0, 1
+                    li   t1, {stack}
+                    mul  t0, t0, t1
+                    la   sp, __boot
+                    add  sp, sp, t0
+                    csrw mscratch, sp
+                    j    {main}
+                 1: wfi
+                    j    1b
+                ",
+                stack = const $stack,
+                harts = const $harts,
+                main  = sym $entry,
+                options(noreturn),
+            )
+        }
+    };
+}
+
+/// 定义热启动入口 `_warm_start`。
+///
+/// 热启动不清零 .bss，也不重新分配启动栈，而是从 `mscratch` 恢复这个硬件线程的机器态栈，
+/// 再以硬件线程号调用高级语言入口。跳转到这里之前，`mscratch` 必须是这个硬件线程的栈顶。
+#[macro_export]
+macro_rules! warm0 {
+    ($entry:ident) => {
+        #[no_mangle]
+        #[naked]
+        unsafe extern "C" fn _warm_start() -> ! {
+            core::arch::asm!(
+                "   csrr sp, mscratch
+                    csrr a0, mhartid
+                    j    {main}
+                ",
                 main = sym $entry,
                 options(noreturn),
             )
@@ -62,8 +108,54 @@ macro_rules! boot0 {
 }
 
 extern "C" {
+    static mut __text: u8;
+    static mut __rodata: u8;
+    static mut __data: u8;
     static mut __sbss: u8;
     static mut __ebss: u8;
+    static mut __boot: u8;
+    static mut __end: u8;
+}
+
+/// 固件的内存布局，每一节的范围包括到下一节为止的对齐填充。
+pub struct Layout {
+    /// .text 节。
+    pub text: Range<usize>,
+    /// .rodata 节。
+    pub rodata: Range<usize>,
+    /// .data 节。
+    pub data: Range<usize>,
+    /// .bss 节。
+    pub bss: Range<usize>,
+    /// 所有硬件线程的启动栈。
+    pub boot: Range<usize>,
+}
+
+/// 查询固件的内存布局。
+pub fn layout() -> Layout {
+    use core::ptr::addr_of;
+    unsafe {
+        let text = addr_of!(__text) as usize;
+        let rodata = addr_of!(__rodata) as usize;
+        let data = addr_of!(__data) as usize;
+        let sbss = addr_of!(__sbss) as usize;
+        let ebss = addr_of!(__ebss) as usize;
+        Layout {
+            text: text..rodata,
+            rodata: rodata..data,
+            data: data..sbss,
+            bss: sbss..ebss,
+            boot: addr_of!(__boot) as usize..addr_of!(__end) as usize,
+        }
+    }
+}
+
+/// 固件镜像占用的范围，从 .text 开头到启动栈末尾。
+///
+/// 引导程序传来的设备树不能放在这里，生成的设备树也要把它写入内存保留表。
+pub fn image() -> Range<usize> {
+    use core::ptr::addr_of;
+    unsafe { addr_of!(__text) as usize..addr_of!(__end) as usize }
 }
 
 /// 清零 .bss。
@@ -0,0 +1,160 @@
+//! 编译时选择的板级描述，用于没有设备树的启动。
+//!
+//! 通过特性 `qemu-virt`、`sifive-u` 或 `spike` 选择，至多选择一个。
+
+use crate::{MachineInfo, MAX_HARTS};
+
+#[cfg(any(
+    all(feature = "qemu-virt", feature = "sifive-u"),
+    all(feature = "qemu-virt", feature = "spike"),
+    all(feature = "sifive-u", feature = "spike"),
+))]
+compile_error!("at most one board profile can be selected");
+
+/// qemu virt，与 `cargo qemu` 的启动参数一致。
+#[cfg(feature = "qemu-virt")]
+mod profile {
+    use super::contexts;
+    use crate::{IsaExtensions, PlicInfo, PmuCounters, PmuInfo, UartInfo};
+    use core::ops::Range;
+
+    pub const MODEL: &str = "riscv-virtio,qemu";
+    pub const SMP: usize = 1;
+    pub const ISA: IsaExtensions = IsaExtensions::H.union(IsaExtensions::SSTC);
+    pub const MEM: Range<usize> = 0x8000_0000..0x8400_0000;
+    pub const UART: UartInfo = UartInfo {
+        reg: 0x1000_0000..0x1000_0100,
+        clock_frequency: 3_686_400,
+        irq: 10,
+        ..UartInfo::NONE
+    };
+    pub const HTIF: bool = false;
+    pub const TEST: Range<usize> = 0x10_0000..0x10_1000;
+    pub const CLINT: Range<usize> = 0x200_0000..0x201_0000;
+    pub const PLIC: PlicInfo = PlicInfo {
+        reg: 0xc00_0000..0xc60_0000,
+        ndev: 0x5f,
+        m_context: contexts(&[0]),
+        s_context: contexts(&[1]),
+    };
+    // 与 qemu 生成的 riscv,pmu 节点一致，默认有 mhpmcounter3 到 mhpmcounter18
+    pub const PMU: PmuInfo = {
+        const HPM: u32 = 0x7fff8;
+        let mut pmu = PmuInfo::NONE;
+        let map = [
+            (0x1, 0x1, HPM | 1 << 0),
+            (0x2, 0x2, HPM | 1 << 2),
+            (0x10019, 0x10019, HPM),
+            (0x1001b, 0x1001b, HPM),
+            (0x10021, 0x10021, HPM),
+        ];
+        while pmu.counters_len < map.len() {
+            let (start, end, counters) = map[pmu.counters_len];
+            pmu.counters[pmu.counters_len] = PmuCounters {
+                start,
+                end,
+                counters,
+            };
+            pmu.counters_len += 1;
+        }
+        pmu
+    };
+    pub const TIMEBASE_FREQUENCY: usize = 10_000_000;
+}
+
+/// qemu sifive_u，只使用最小的 2 个核和 64 MiB 内存。
+#[cfg(feature = "sifive-u")]
+mod profile {
+    use super::contexts;
+    use crate::{IsaExtensions, PlicInfo, PmuInfo, UartInfo, UartModel};
+    use core::ops::Range;
+
+    pub const MODEL: &str = "SiFive HiFive Unleashed A00";
+    pub const SMP: usize = 2;
+    pub const ISA: IsaExtensions = IsaExtensions::NONE;
+    pub const MEM: Range<usize> = 0x8000_0000..0x8400_0000;
+    pub const UART: UartInfo = UartInfo {
+        model: UartModel::SifiveUart0,
+        reg: 0x1001_0000..0x1001_1000,
+        irq: 4,
+        ..UartInfo::NONE
+    };
+    pub const HTIF: bool = false;
+    pub const TEST: Range<usize> = 0x10_0000..0x10_1000;
+    pub const CLINT: Range<usize> = 0x200_0000..0x201_0000;
+    // 0 号核是没有监管态的 E51
+    pub const PLIC: PlicInfo = PlicInfo {
+        reg: 0xc00_0000..0x1000_0000,
+        ndev: 0x35,
+        m_context: contexts(&[0, 1]),
+        s_context: {
+            let mut s = contexts(&[]);
+            s[1] = Some(2);
+            s
+        },
+    };
+    pub const PMU: PmuInfo = PmuInfo::NONE;
+    pub const TIMEBASE_FREQUENCY: usize = 1_000_000;
+}
+
+/// spike 的默认配置。spike 没有串口和测试设备，通过 HTIF 交互。
+#[cfg(feature = "spike")]
+mod profile {
+    use crate::{IsaExtensions, PlicInfo, PmuInfo, UartInfo};
+    use core::ops::Range;
+
+    pub const MODEL: &str = "ucbbar,spike-bare";
+    pub const SMP: usize = 1;
+    pub const ISA: IsaExtensions = IsaExtensions::NONE;
+    pub const MEM: Range<usize> = 0x8000_0000..0x1_0000_0000;
+    pub const UART: UartInfo = UartInfo::NONE;
+    pub const HTIF: bool = true;
+    pub const TEST: Range<usize> = 0..0;
+    pub const CLINT: Range<usize> = 0x200_0000..0x20c_0000;
+    pub const PLIC: PlicInfo = PlicInfo::NONE;
+    pub const PMU: PmuInfo = PmuInfo::NONE;
+    pub const TIMEBASE_FREQUENCY: usize = 10_000_000;
+}
+
+/// 按硬件线程顺序列出上下文号。
+#[allow(dead_code)]
+const fn contexts(list: &[u16]) -> [Option<u16>; MAX_HARTS] {
+    let mut ans = [None; MAX_HARTS];
+    let mut i = 0;
+    while i < list.len() {
+        ans[i] = Some(list[i]);
+        i += 1;
+    }
+    ans
+}
+
+impl MachineInfo {
+    /// 使用编译时选择的板级描述构造机器信息。
+    ///
+    /// 未选择任何板级描述时返回 `None`。
+    pub fn from_board() -> Option<Self> {
+        #[cfg(any(feature = "qemu-virt", feature = "sifive-u", feature = "spike"))]
+        {
+            use profile::*;
+            Some(Self {
+                dtb: 0..0,
+                model: crate::InlineString::new(MODEL),
+                smp: SMP,
+                isa: ISA,
+                mem: MEM,
+                uart: UART,
+                htif: HTIF,
+                test: TEST,
+                clint: CLINT,
+                sswi: 0..0,
+                plic: PLIC,
+                pmu: PMU,
+                timebase_frequency: TIMEBASE_FREQUENCY,
+            })
+        }
+        #[cfg(not(any(feature = "qemu-virt", feature = "sifive-u", feature = "spike")))]
+        {
+            None
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! 从机器信息生成扁平设备树，交给没有收到设备树的内核。
+
+use crate::{relocate, DtbError, MachineInfo, UartModel, DTB_BUFFER_SIZE, MAX_HARTS};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+};
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+
+impl MachineInfo {
+    /// 根据机器信息生成设备树，写入固件缓冲区，并更新 [`MachineInfo::dtb`]。
+    ///
+    /// `reserved` 中的范围写入内存保留表，内核不会使用它们，一般用于保护固件自身。
+    ///
+    /// 固件缓冲区同时用于重定位设备树，所以只应在没有设备树时调用。
+    pub fn generate_dtb(
+        &mut self,
+        boot_hart: usize,
+        reserved: &[Range<usize>],
+    ) -> Result<(), DtbError> {
+        let buf = unsafe { core::slice::from_raw_parts_mut(relocate::buffer(), DTB_BUFFER_SIZE) };
+        let mut w = Writer {
+            buf,
+            len: 0,
+            strings: [0; 256],
+            strings_len: 0,
+            overflow: false,
+        };
+        // 头部稍后回填
+        w.len = relocate::FDT_HEADER_LEN;
+        // 内存保留表
+        let off_mem_rsvmap = w.len;
+        for r in reserved {
+            w.u64(r.start as _);
+            w.u64((r.end - r.start) as _);
+        }
+        w.u64(0);
+        w.u64(0);
+        // 结构块
+        let off_dt_struct = w.len;
+        self.write_tree(&mut w);
+        w.u32(FDT_END);
+        let size_dt_struct = w.len - off_dt_struct;
+        // 字符串块
+        let off_dt_strings = w.len;
+        let strings = w.strings;
+        w.raw(&strings[..w.strings_len]);
+        let total_size = w.len;
+
+        if w.overflow {
+            return Err(DtbError::TooLarge(total_size));
+        }
+        for (i, field) in [
+            relocate::FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17, // version
+            16, // last_comp_version
+            boot_hart as u32,
+            w.strings_len as u32,
+            size_dt_struct as u32,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            w.buf[i * 4..][..4].copy_from_slice(&field.to_be_bytes());
+        }
+        self.dtb = w.buf.as_ptr() as usize..w.buf.as_ptr() as usize + total_size;
+        Ok(())
+    }
+
+    fn write_tree(&self, w: &mut Writer) {
+        // 核内中断控制器的 phandle 是 1..=smp，PLIC 排在它们之后
+        let plic_phandle = self.smp as u32 + 1;
+        w.begin_node(format_args!(""));
+        w.prop_u32("#address-cells", 2);
+        w.prop_u32("#size-cells", 2);
+        w.prop_str("model", self.model.as_str());
+        if !self.uart.reg.is_empty() {
+            w.begin_node(format_args!("chosen"));
+            w.prop("stdout-path", |w| {
+                let _ = write!(w, "/soc/serial@{:x}\0", self.uart.reg.start);
+            });
+            w.end_node();
+        }
+
+        if self.htif {
+            w.begin_node(format_args!("htif"));
+            w.prop_str("compatible", "ucb,htif0");
+            w.end_node();
+        }
+
+        w.begin_node(format_args!("cpus"));
+        w.prop_u32("#address-cells", 1);
+        w.prop_u32("#size-cells", 0);
+        w.prop_u32("timebase-frequency", self.timebase_frequency as _);
+        for hart in 0..self.smp {
+            w.begin_node(format_args!("cpu@{hart}"));
+            w.prop_str("device_type", "cpu");
+            w.prop_u32("reg", hart as _);
+            w.prop_str("status", "okay");
+            w.prop_str("compatible", "riscv");
+            w.prop("riscv,isa", |w| {
+                // 单字母扩展接在基础 ISA 后面，多字母扩展用下划线分隔
+                let _ = write!(w, "rv64imafdc");
+                for name in self.isa.names() {
+                    let _ = match name.len() {
+                        1 => write!(w, "{name}"),
+                        _ => write!(w, "_{name}"),
+                    };
+                }
+                w.raw(&[0]);
+            });
+            w.prop_str("mmu-type", "riscv,sv39");
+            w.begin_node(format_args!("interrupt-controller"));
+            w.prop_u32("#interrupt-cells", 1);
+            w.prop("interrupt-controller", |_| {});
+            w.prop_str("compatible", "riscv,cpu-intc");
+            w.prop_u32("phandle", hart as u32 + 1);
+            w.end_node();
+            w.end_node();
+        }
+        w.end_node();
+
+        w.begin_node(format_args!("memory@{:x}", self.mem.start));
+        w.prop_str("device_type", "memory");
+        w.prop_reg(&self.mem);
+        w.end_node();
+
+        w.begin_node(format_args!("soc"));
+        w.prop_u32("#address-cells", 2);
+        w.prop_u32("#size-cells", 2);
+        w.prop_str("compatible", "simple-bus");
+        w.prop("ranges", |_| {});
+        if !self.uart.reg.is_empty() {
+            w.begin_node(format_args!("serial@{:x}", self.uart.reg.start));
+            w.prop_str(
+                "compatible",
+                match self.uart.model {
+                    UartModel::Ns16550a => "ns16550a",
+                    UartModel::SifiveUart0 => "sifive,uart0",
+                },
+            );
+            w.prop_reg(&self.uart.reg);
+            if self.uart.clock_frequency != 0 {
+                w.prop_u32("clock-frequency", self.uart.clock_frequency);
+            }
+            if self.uart.reg_shift != 0 {
+                w.prop_u32("reg-shift", self.uart.reg_shift);
+            }
+            if self.uart.reg_io_width != 1 {
+                w.prop_u32("reg-io-width", self.uart.reg_io_width);
+            }
+            if self.uart.irq != 0 && !self.plic.reg.is_empty() {
+                w.prop_u32("interrupt-parent", plic_phandle);
+                w.prop_u32("interrupts", self.uart.irq);
+            }
+            w.end_node();
+        }
+        if !self.test.is_empty() {
+            w.begin_node(format_args!("test@{:x}", self.test.start));
+            w.prop("compatible", |w| {
+                w.raw(b"sifive,test1\0sifive,test0\0syscon\0")
+            });
+            w.prop_reg(&self.test);
+            w.end_node();
+        }
+        if !self.clint.is_empty() {
+            w.begin_node(format_args!("clint@{:x}", self.clint.start));
+            w.prop("compatible", |w| w.raw(b"sifive,clint0\0riscv,clint0\0"));
+            w.prop_reg(&self.clint);
+            // 每个硬件线程的 M 态软件中断和 M 态时钟中断
+            w.prop("interrupts-extended", |w| {
+                for hart in 0..self.smp {
+                    w.u32(hart as u32 + 1);
+                    w.u32(3);
+                    w.u32(hart as u32 + 1);
+                    w.u32(7);
+                }
+            });
+            w.end_node();
+        }
+        if !self.sswi.is_empty() {
+            w.begin_node(format_args!("sswi@{:x}", self.sswi.start));
+            w.prop_str("compatible", "riscv,aclint-sswi");
+            w.prop_u32("#interrupt-cells", 0);
+            w.prop("interrupt-controller", |_| {});
+            w.prop_reg(&self.sswi);
+            // 每个硬件线程的 S 态软件中断
+            w.prop("interrupts-extended", |w| {
+                for hart in 0..self.smp {
+                    w.u32(hart as u32 + 1);
+                    w.u32(1);
+                }
+            });
+            w.end_node();
+        }
+        if !self.plic.reg.is_empty() {
+            w.begin_node(format_args!("plic@{:x}", self.plic.reg.start));
+            w.prop_str("compatible", "riscv,plic0");
+            w.prop_u32("#interrupt-cells", 1);
+            w.prop("interrupt-controller", |_| {});
+            w.prop_reg(&self.plic.reg);
+            w.prop_u32("riscv,ndev", self.plic.ndev);
+            w.prop_u32("phandle", plic_phandle);
+            // 按上下文号排列 (核内中断控制器, 中断号)，空缺的上下文填 -1
+            w.prop("interrupts-extended", |w| {
+                let mut pairs = [(u32::MAX, u32::MAX); 2 * MAX_HARTS];
+                let mut count = 0;
+                for hart in 0..MAX_HARTS {
+                    let contexts = [
+                        (self.plic.m_context[hart], 11),
+                        (self.plic.s_context[hart], 9),
+                    ];
+                    for (ctx, irq) in contexts {
+                        if let Some(ctx) = ctx.map(usize::from).filter(|c| *c < pairs.len()) {
+                            pairs[ctx] = (hart as u32 + 1, irq);
+                            count = count.max(ctx + 1);
+                        }
+                    }
+                }
+                for (phandle, irq) in &pairs[..count] {
+                    w.u32(*phandle);
+                    w.u32(*irq);
+                }
+            });
+            w.end_node();
+        }
+        w.end_node();
+
+        w.end_node();
+    }
+}
+
+/// 在缓冲区上顺序写设备树。
+///
+/// 写满后只记录溢出，最后统一报告，以免每一步都要处理错误。
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    strings: [u8; 256],
+    strings_len: usize,
+    overflow: bool,
+}
+
+impl Writer<'_> {
+    fn raw(&mut self, bytes: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + bytes.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn u32(&mut self, val: u32) {
+        self.raw(&val.to_be_bytes());
+    }
+
+    fn u64(&mut self, val: u64) {
+        self.raw(&val.to_be_bytes());
+    }
+
+    /// 按 4 字节对齐，用 0 填充。
+    fn align(&mut self) {
+        while self.len % 4 != 0 && !self.overflow {
+            self.raw(&[0]);
+        }
+    }
+
+    fn begin_node(&mut self, name: fmt::Arguments) {
+        self.u32(FDT_BEGIN_NODE);
+        let _ = self.write_fmt(name);
+        self.raw(&[0]);
+        self.align();
+    }
+
+    fn end_node(&mut self) {
+        self.u32(FDT_END_NODE);
+    }
+
+    /// 写一个属性，值由 `value` 写入，长度自动回填。
+    fn prop(&mut self, name: &str, value: impl FnOnce(&mut Self)) {
+        self.u32(FDT_PROP);
+        let len_at = self.len;
+        self.u32(0);
+        let name_offset = self.string(name);
+        self.u32(name_offset);
+        let start = self.len;
+        value(self);
+        if let Some(dst) = self.buf.get_mut(len_at..len_at + 4) {
+            dst.copy_from_slice(&((self.len - start) as u32).to_be_bytes());
+        }
+        self.align();
+    }
+
+    fn prop_u32(&mut self, name: &str, val: u32) {
+        self.prop(name, |w| w.u32(val));
+    }
+
+    fn prop_str(&mut self, name: &str, val: &str) {
+        self.prop(name, |w| {
+            w.raw(val.as_bytes());
+            w.raw(&[0]);
+        });
+    }
+
+    /// 写 `reg` 属性，地址和长度各占 2 个单元。
+    fn prop_reg(&mut self, range: &Range<usize>) {
+        self.prop("reg", |w| {
+            w.u64(range.start as _);
+            w.u64((range.end - range.start) as _);
+        });
+    }
+
+    /// 找到或追加属性名，返回它在字符串块中的偏移。
+    fn string(&mut self, name: &str) -> u32 {
+        let name = name.as_bytes();
+        let mut offset = 0;
+        while offset < self.strings_len {
+            let len = self.strings[offset..].iter().position(|b| *b == 0).unwrap();
+            if &self.strings[offset..][..len] == name {
+                return offset as _;
+            }
+            offset += len + 1;
+        }
+        match self.strings.get_mut(offset..offset + name.len() + 1) {
+            Some(dst) => {
+                dst[..name.len()].copy_from_slice(name);
+                dst[name.len()] = 0;
+                self.strings_len += name.len() + 1;
+            }
+            None => self.overflow = true,
+        }
+        offset as _
+    }
+}
+
+impl Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.raw(s.as_bytes());
+        Ok(())
+    }
+}
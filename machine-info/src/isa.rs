@@ -0,0 +1,111 @@
+//! 设备树描述的 ISA 扩展。
+
+use core::{
+    fmt::{self, Display, Formatter},
+    ops::BitAnd,
+};
+
+/// 固件关心的 ISA 扩展集合。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IsaExtensions(u32);
+
+/// 扩展名与对应的位，按设备树中的写法。
+const NAMES: [(IsaExtensions, &str); 7] = [
+    (IsaExtensions::H, "h"),
+    (IsaExtensions::SSTC, "sstc"),
+    (IsaExtensions::SSCOFPMF, "sscofpmf"),
+    (IsaExtensions::SVADU, "svadu"),
+    (IsaExtensions::ZICFILP, "zicfilp"),
+    (IsaExtensions::ZICFISS, "zicfiss"),
+    (IsaExtensions::SSDBLTRP, "ssdbltrp"),
+];
+
+impl IsaExtensions {
+    /// 空集。
+    pub const NONE: Self = Self(0);
+    /// 虚拟化扩展。
+    pub const H: Self = Self(1 << 0);
+    /// S 态定时器比较寄存器 `stimecmp`。
+    pub const SSTC: Self = Self(1 << 1);
+    /// 计数器溢出中断和按特权级过滤。
+    pub const SSCOFPMF: Self = Self(1 << 2);
+    /// 硬件更新页表项 A/D 位。
+    pub const SVADU: Self = Self(1 << 3);
+    /// 控制流完整性：着陆点。
+    pub const ZICFILP: Self = Self(1 << 4);
+    /// 控制流完整性：影子栈。
+    pub const ZICFISS: Self = Self(1 << 5);
+    /// S 态双重陷入。
+    pub const SSDBLTRP: Self = Self(1 << 6);
+
+    /// 判断是否包含 `other` 中的所有扩展。
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// 并集。
+    #[inline]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// 解析 `riscv,isa` 属性，形如 `rv64imafdch_zicsr_sstc`。
+    ///
+    /// 单字母扩展写在基础 ISA 之后，多字母扩展用下划线分隔。
+    pub fn from_isa_string(isa: &[u8]) -> Self {
+        let isa = isa.split(|c| *c == 0).next().unwrap_or(&[]);
+        let mut parts = isa.split(|c| *c == b'_');
+        // 跳过 `rv64`，之后每个字母是一个扩展
+        let single = parts.next().unwrap_or(&[]).get(4..).unwrap_or(&[]);
+        let mut ans = Self::NONE;
+        if single.contains(&b'h') {
+            ans = ans.union(Self::H);
+        }
+        parts.fold(ans, |ans, name| ans.union(Self::from_name(name)))
+    }
+
+    /// 解析 `riscv,isa-extensions` 属性，每个扩展名是一个字符串。
+    pub fn from_string_list(list: &[u8]) -> Self {
+        list.split(|c| *c == 0)
+            .fold(Self::NONE, |ans, name| ans.union(Self::from_name(name)))
+    }
+
+    /// 集合中各扩展的名字。
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        NAMES
+            .iter()
+            .filter(move |(ext, _)| self.contains(*ext))
+            .map(|(_, name)| *name)
+    }
+
+    fn from_name(name: &[u8]) -> Self {
+        NAMES
+            .iter()
+            .find(|(_, n)| n.as_bytes().eq_ignore_ascii_case(name))
+            .map_or(Self::NONE, |(ext, _)| *ext)
+    }
+}
+
+impl BitAnd for IsaExtensions {
+    type Output = Self;
+
+    #[inline]
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+/// 以 `_` 分隔列出扩展名，空集输出 `-`。
+impl Display for IsaExtensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut names = self.names();
+        match names.next() {
+            Some(first) => {
+                write!(f, "{first}")?;
+                names.try_for_each(|name| write!(f, "_{name}"))
+            }
+            None => write!(f, "-"),
+        }
+    }
+}
@@ -1,45 +1,253 @@
 //! 这个项目用于从设备树解析硬件信息。
+//!
+//! 没有设备树时，也可以使用编译时选择的板级描述，见 [`MachineInfo::from_board`]。
 
 #![no_std]
 #![deny(warnings, missing_docs)]
 
+mod board;
+mod fdt;
+mod isa;
+mod pmu;
+mod relocate;
+mod report;
+
+pub use isa::IsaExtensions;
+pub use pmu::{PmuCounters, PmuInfo, PmuRawCounters, PmuSelector, MAX_PMU_ENTRIES};
+pub use relocate::{DtbError, DTB_BUFFER_SIZE};
+pub use report::Dump;
+
 use core::{
-    fmt::{Display, Formatter, Result},
+    fmt::{self, Debug, Display, Formatter},
     ops::Range,
 };
 
+/// 按硬件线程记录的信息最多支持的硬件线程数。
+pub const MAX_HARTS: usize = 8;
+
 /// 从设备树采集的板信息。
 pub struct MachineInfo {
     /// 设备树地址范围。
+    ///
+    /// 设备树被重定位时，这里是重定位后的位置。
     pub dtb: Range<usize>,
     /// 机器型号。
     pub model: InlineString<64>,
     /// CPU 核数。
     pub smp: usize,
+    /// 所有硬件线程都支持的 ISA 扩展。
+    pub isa: IsaExtensions,
     /// 内存地址范围。
     pub mem: Range<usize>,
-    /// 串口地址范围。
-    pub uart: Range<usize>,
+    /// 串口信息。
+    pub uart: UartInfo,
+    /// 是否通过 HTIF 与宿主机交互。
+    pub htif: bool,
     /// TestDevice 地址范围。
     pub test: Range<usize>,
     /// CLINT 地址范围。
     pub clint: Range<usize>,
+    /// ACLINT SSWI 地址范围，写入后直接产生 S 态软件中断。
+    pub sswi: Range<usize>,
+    /// PLIC 信息。
+    pub plic: PlicInfo,
+    /// PMU 事件和计数器的对应关系。
+    pub pmu: PmuInfo,
+    /// `mtime` 的频率。
+    pub timebase_frequency: usize,
+}
+
+/// 串口型号。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UartModel {
+    /// ns16550a 兼容串口。
+    Ns16550a,
+    /// SiFive 串口，兼容字符串为 `sifive,uart0`。
+    SifiveUart0,
+}
+
+/// 串口信息。
+pub struct UartInfo {
+    /// 串口型号。
+    pub model: UartModel,
+    /// 寄存器地址范围。
+    pub reg: Range<usize>,
+    /// 输入时钟频率，0 表示未知。
+    pub clock_frequency: u32,
+    /// 寄存器序号转换为地址偏移时左移的位数。
+    pub reg_shift: u32,
+    /// 寄存器访问宽度，以字节为单位。
+    pub reg_io_width: u32,
+    /// PLIC 中断号，0 表示没有中断。
+    pub irq: u32,
+}
+
+impl UartInfo {
+    /// 未发现串口。
+    pub const NONE: Self = Self {
+        model: UartModel::Ns16550a,
+        reg: 0..0,
+        clock_frequency: 0,
+        reg_shift: 0,
+        reg_io_width: 1,
+        irq: 0,
+    };
+}
+
+/// PLIC 信息。
+pub struct PlicInfo {
+    /// 寄存器地址范围。
+    pub reg: Range<usize>,
+    /// 中断源的数量，即 `riscv,ndev`。
+    pub ndev: u32,
+    /// 每个硬件线程的机器态外部中断对应的上下文号。
+    pub m_context: [Option<u16>; MAX_HARTS],
+    /// 每个硬件线程的监管态外部中断对应的上下文号。
+    pub s_context: [Option<u16>; MAX_HARTS],
+}
+
+impl PlicInfo {
+    /// 未发现 PLIC。
+    pub const NONE: Self = Self {
+        reg: 0..0,
+        ndev: 0,
+        m_context: [None; MAX_HARTS],
+        s_context: [None; MAX_HARTS],
+    };
+
+    /// 解析 PLIC 的 `interrupts-extended` 属性。
+    ///
+    /// 属性由 (核内中断控制器, 中断号) 对构成，下标就是上下文号，
+    /// 中断号 11 是机器态外部中断，9 是监管态外部中断。
+    /// `intc` 按硬件线程号记录各硬件线程核内中断控制器的 phandle，0 表示未知。
+    fn parse_contexts(&mut self, value: &[u8], intc: &[u32; MAX_HARTS]) {
+        for (i, pair) in value.chunks_exact(8).enumerate() {
+            let phandle = be_cells(&pair[..4]) as u32;
+            if phandle == 0 {
+                continue;
+            }
+            if let Some(hart) = intc.iter().position(|p| *p == phandle) {
+                match be_cells(&pair[4..]) {
+                    11 => self.m_context[hart] = Some(i as _),
+                    9 => self.s_context[hart] = Some(i as _),
+                    _ => {}
+                }
+            }
+        }
+    }
 }
 
 /// 原地存储的有限长度字符串。
+///
+/// 内容总是合法的 UTF-8，超出容量的部分在字符边界处截断。
 pub struct InlineString<const N: usize>(usize, [u8; N]);
 
+impl<const N: usize> InlineString<N> {
+    /// 空字符串。
+    pub const EMPTY: Self = Self(0, [0u8; N]);
+
+    /// 从字符串构造，超过 `N` 字节时截断。
+    pub fn new(s: &str) -> Self {
+        let mut ans = Self::EMPTY;
+        ans.push_str(s);
+        ans
+    }
+
+    /// 从设备树等不可信来源的字节构造。
+    ///
+    /// 只保留第一个非法 UTF-8 序列之前的部分，超过 `N` 字节时截断。
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let valid = match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        };
+        Self::new(valid)
+    }
+
+    /// 以字符串切片形式访问。
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        // SAFETY: 只通过 `push_str` 写入，内容总是合法的 UTF-8。
+        unsafe { core::str::from_utf8_unchecked(&self.1[..self.0]) }
+    }
+
+    /// 追加字符串，容量不足时在字符边界处截断。
+    ///
+    /// 返回是否完整追加。
+    pub fn push_str(&mut self, s: &str) -> bool {
+        let mut len = s.len().min(N - self.0);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.1[self.0..][..len].copy_from_slice(&s.as_bytes()[..len]);
+        self.0 += len;
+        len == s.len()
+    }
+}
+
+impl<const N: usize> Default for InlineString<N> {
+    #[inline]
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl<const N: usize> fmt::Write for InlineString<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.push_str(s) {
+            Ok(())
+        } else {
+            Err(fmt::Error)
+        }
+    }
+}
+
 impl<const N: usize> Display for InlineString<N> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        write!(f, "{}", unsafe {
-            core::str::from_utf8_unchecked(&self.1[..self.0])
-        })
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl<const N: usize> Debug for InlineString<N> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(self.as_str(), f)
     }
 }
 
 impl MachineInfo {
+    /// 收集机器信息。
+    ///
+    /// 设备树有效时从设备树解析，否则使用编译时选择的板级描述。
+    /// 两者都不可用时，返回解析设备树的错误。
+    pub fn detect(dtb_ptr: usize, reserved: &[Range<usize>]) -> Result<Self, DtbError> {
+        Self::from_dtb(dtb_ptr, reserved).or_else(|e| Self::from_board().ok_or(e))
+    }
+
     /// 从设备树解析机器信息。
-    pub fn from_dtb(dtb_ptr: usize) -> Self {
+    ///
+    /// 设备树未按 8 字节对齐、与 `reserved` 中的任何范围（例如内核的加载区域）重叠，
+    /// 或不在内存范围内时，将被复制到固件自有的对齐缓冲区。
+    /// [`MachineInfo::dtb`] 记录设备树的最终位置。
+    pub fn from_dtb(dtb_ptr: usize, reserved: &[Range<usize>]) -> Result<Self, DtbError> {
+        let size = relocate::total_size(dtb_ptr)?;
+        let ptr = if relocate::usable_in_place(&(dtb_ptr..dtb_ptr + size), reserved) {
+            dtb_ptr
+        } else {
+            relocate::copy(dtb_ptr, size)?
+        };
+        let mut ans = Self::walk(ptr)?;
+        // 引导程序可能把设备树放在内存以外，比如只读存储器里，内核未必能访问。
+        if ptr == dtb_ptr && !relocate::contains(&ans.mem, &ans.dtb) {
+            ans.dtb.start = relocate::copy(ptr, size)?;
+            ans.dtb.end = ans.dtb.start + size;
+        }
+        Ok(ans)
+    }
+
+    /// 遍历已检查过位置的设备树。
+    fn walk(dtb_ptr: usize) -> Result<Self, DtbError> {
         use dtb_walker::{Dtb, DtbObj, HeaderError as E, Property, Str, WalkOperation::*};
 
         const CPUS: &str = "cpus";
@@ -49,72 +257,216 @@ impl MachineInfo {
         const SERIAL: &str = "serial";
         const TEST: &str = "test";
         const CLINT: &str = "clint";
+        const SSWI: &str = "sswi";
+        const HTIF: &str = "htif";
+        const PLIC: &str = "plic";
+        const INTC: &str = "interrupt-controller";
+        const PMU: &str = "pmu";
 
         let mut ans = Self {
             dtb: dtb_ptr..dtb_ptr,
-            model: InlineString(0, [0u8; 64]),
+            model: InlineString::EMPTY,
             smp: 0,
+            isa: IsaExtensions::NONE,
             mem: 0..0,
-            uart: 0..0,
+            uart: UartInfo::NONE,
+            htif: false,
             test: 0..0,
             clint: 0..0,
+            sswi: 0..0,
+            plic: PlicInfo::NONE,
+            pmu: PmuInfo::NONE,
+            timebase_frequency: 0,
         };
         let dtb = unsafe {
-            Dtb::from_raw_parts_filtered(dtb_ptr as _, |e| {
-                matches!(e, E::Misaligned(4) | E::LastCompVersion(_))
-            })
+            Dtb::from_raw_parts_filtered(dtb_ptr as _, |e| matches!(e, E::LastCompVersion(_)))
         }
-        .unwrap();
+        .map_err(|_| DtbError::Invalid)?;
         ans.dtb.end += dtb.total_size();
+        // 正在解析的硬件线程的 ISA 扩展，离开时与之前的取交集
+        let mut hart_isa = IsaExtensions::NONE;
+        // 正在解析的硬件线程号，以及各硬件线程核内中断控制器的 phandle
+        let mut hart = None;
+        let mut intc = [0u32; MAX_HARTS];
         dtb.walk(|ctx, obj| match obj {
             DtbObj::SubNode { name } => {
                 let current = ctx.name();
                 if ctx.is_root() {
-                    if name == Str::from(CPUS) || name == Str::from(SOC) || name.starts_with(MEMORY)
+                    // 新版 qemu 把 PMU 节点放在根节点下，旧版放在 soc 下
+                    if name == Str::from(CPUS)
+                        || name == Str::from(SOC)
+                        || name.starts_with(MEMORY)
+                        || name == Str::from(PMU)
                     {
                         StepInto
+                    } else {
+                        if name == Str::from(HTIF) {
+                            ans.htif = true;
+                        }
+                        StepOver
+                    }
+                } else if current == Str::from(SOC)
+                    && (name.starts_with(UART) || name.starts_with(SERIAL))
+                {
+                    // 只采用第一个串口
+                    if ans.uart.reg.is_empty() {
+                        StepInto
                     } else {
                         StepOver
                     }
                 } else if current == Str::from(SOC)
-                    && ["uart", "serial", "test", "clint"]
+                    && [TEST, CLINT, SSWI, PLIC, INTC, PMU]
                         .iter()
                         .any(|pre| name.starts_with(pre))
                 {
                     StepInto
-                } else {
-                    if current == Str::from(CPUS) && name.starts_with("cpu@") {
-                        ans.smp += 1;
+                } else if current == Str::from(CPUS) && name.starts_with("cpu@") {
+                    if ans.smp > 0 {
+                        ans.isa = merge(ans.isa, hart_isa, ans.smp);
                     }
+                    ans.smp += 1;
+                    hart_isa = IsaExtensions::NONE;
+                    hart = None;
+                    StepInto
+                } else if current.starts_with("cpu@") && name == Str::from(INTC) {
+                    StepInto
+                } else {
                     StepOver
                 }
             }
             DtbObj::Property(Property::Model(model)) if ctx.is_root() => {
-                ans.model.0 = model.as_bytes().len();
-                ans.model.1[..ans.model.0].copy_from_slice(model.as_bytes());
+                ans.model = InlineString::from_bytes(model.as_bytes());
+                StepOver
+            }
+            DtbObj::Property(Property::Compatible(mut compatible))
+                if ctx.name().starts_with(UART) || ctx.name().starts_with(SERIAL) =>
+            {
+                if compatible.any(|c| c == Str::from("sifive,uart0")) {
+                    ans.uart.model = UartModel::SifiveUart0;
+                }
                 StepOver
             }
             DtbObj::Property(Property::Reg(mut reg)) => {
                 let node = ctx.name();
                 if node.starts_with(UART) || node.starts_with(SERIAL) {
-                    ans.uart = reg.next().unwrap();
-                    StepOut
+                    // 串口还有其他属性要解析，不能跳出
+                    ans.uart.reg = reg.next().unwrap();
+                    StepOver
                 } else if node.starts_with(TEST) {
                     ans.test = reg.next().unwrap();
                     StepOut
                 } else if node.starts_with(CLINT) {
                     ans.clint = reg.next().unwrap();
                     StepOut
+                } else if node.starts_with(SSWI) {
+                    ans.sswi = reg.next().unwrap();
+                    StepOut
                 } else if node.starts_with(MEMORY) {
                     ans.mem = reg.next().unwrap();
                     StepOut
+                } else if node.starts_with(PLIC) || node.starts_with(INTC) {
+                    ans.plic.reg = reg.next().unwrap();
+                    StepOver
+                } else if node.starts_with("cpu@") {
+                    // 核内中断控制器在子节点里，不能跳出
+                    hart = reg.next().map(|r| r.start).filter(|h| *h < MAX_HARTS);
+                    StepOver
                 } else {
                     StepOver
                 }
             }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name().starts_with("cpu@") =>
+            {
+                if name == Str::from("riscv,isa") {
+                    hart_isa = hart_isa.union(IsaExtensions::from_isa_string(value));
+                } else if name == Str::from("riscv,isa-extensions") {
+                    hart_isa = hart_isa.union(IsaExtensions::from_string_list(value));
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(CPUS) && name == Str::from("timebase-frequency") =>
+            {
+                ans.timebase_frequency = be_cells(value);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name().starts_with(UART) || ctx.name().starts_with(SERIAL) =>
+            {
+                if name == Str::from("clock-frequency") {
+                    ans.uart.clock_frequency = be_cells(value) as _;
+                } else if name == Str::from("reg-shift") {
+                    ans.uart.reg_shift = be_cells(value) as _;
+                } else if name == Str::from("reg-io-width") {
+                    ans.uart.reg_io_width = be_cells(value) as _;
+                } else if name == Str::from("interrupts") {
+                    // 只取第一个中断号，属性过短时忽略
+                    if let Some(irq) = value.get(..4) {
+                        ans.uart.irq = be_cells(irq) as _;
+                    }
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if (ctx.name().starts_with(PLIC) || ctx.name().starts_with(INTC))
+                    && name == Str::from("riscv,ndev") =>
+            {
+                ans.plic.ndev = be_cells(value) as _;
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value }) if ctx.name().starts_with(PMU) => {
+                ans.pmu.parse(name.as_bytes(), value);
+                StepOver
+            }
+            DtbObj::Property(Property::PHandle(phandle)) if ctx.name() == Str::from(INTC) => {
+                if let Some(hart) = hart {
+                    intc[hart] = phandle.value();
+                }
+                StepOver
+            }
             DtbObj::Property(_) => StepOver,
         });
+        if ans.smp > 0 {
+            ans.isa = merge(ans.isa, hart_isa, ans.smp);
+        }
+        // PLIC 可能出现在 cpus 之前，收集完核内中断控制器的 phandle 后再遍历一次解析上下文
+        if !ans.plic.reg.is_empty() {
+            dtb.walk(|ctx, obj| match obj {
+                DtbObj::SubNode { name } => {
+                    if ctx.is_root() && name == Str::from(SOC)
+                        || ctx.name() == Str::from(SOC)
+                            && (name.starts_with(PLIC) || name.starts_with(INTC))
+                    {
+                        StepInto
+                    } else {
+                        StepOver
+                    }
+                }
+                DtbObj::Property(Property::General { name, value })
+                    if name == Str::from("interrupts-extended") =>
+                {
+                    ans.plic.parse_contexts(value, &intc);
+                    StepOut
+                }
+                DtbObj::Property(_) => StepOver,
+            });
+        }
+        Ok(ans)
+    }
+}
 
-        ans
+/// 把第 `n` 个硬件线程的 ISA 扩展并入之前的交集。
+#[inline]
+fn merge(isa: IsaExtensions, hart: IsaExtensions, n: usize) -> IsaExtensions {
+    if n == 1 {
+        hart
+    } else {
+        isa & hart
     }
 }
+
+/// 解析由 1 或 2 个大端序单元构成的数值属性。
+fn be_cells(value: &[u8]) -> usize {
+    value.iter().fold(0, |acc, b| (acc << 8) | *b as usize)
+}
@@ -0,0 +1,163 @@
+//! 设备树 `riscv,pmu` 节点描述的事件和计数器的对应关系。
+//!
+//! 计数器位图按计数器 CSR 的序号排列，第 0 位是 `mcycle`，第 2 位是 `minstret`，
+//! 第 3 到 31 位是 `mhpmcounter3` 到 `mhpmcounter31`。
+
+use crate::be_cells;
+
+/// 每种映射最多记录的条目数。
+pub const MAX_PMU_ENTRIES: usize = 16;
+
+/// 一段事件号可以使用的计数器，来自 `riscv,event-to-mhpmcounters`。
+#[derive(Clone, Copy, Debug)]
+pub struct PmuCounters {
+    /// 第一个事件号。
+    pub start: u32,
+    /// 最后一个事件号，包含在范围内。
+    pub end: u32,
+    /// 可以计数这些事件的计数器位图。
+    pub counters: u32,
+}
+
+/// 事件对应的 `mhpmevent` 值，来自 `riscv,event-to-mhpmevent`。
+#[derive(Clone, Copy, Debug)]
+pub struct PmuSelector {
+    /// 事件号。
+    pub event: u32,
+    /// 写入 `mhpmevent` 的值。
+    pub selector: u64,
+}
+
+/// 原始事件可以使用的计数器，来自 `riscv,raw-event-to-mhpmcounters`。
+#[derive(Clone, Copy, Debug)]
+pub struct PmuRawCounters {
+    /// 与 `mask` 相与后匹配的值。
+    pub value: u64,
+    /// 参与匹配的位。
+    pub mask: u64,
+    /// 可以计数这些事件的计数器位图。
+    pub counters: u32,
+}
+
+/// PMU 信息。
+#[derive(Clone, Copy)]
+pub struct PmuInfo {
+    /// 事件号到计数器的映射。
+    pub counters: [PmuCounters; MAX_PMU_ENTRIES],
+    /// `counters` 中有效的条目数。
+    pub counters_len: usize,
+    /// 事件号到 `mhpmevent` 值的映射。
+    pub selectors: [PmuSelector; MAX_PMU_ENTRIES],
+    /// `selectors` 中有效的条目数。
+    pub selectors_len: usize,
+    /// 原始事件到计数器的映射。
+    pub raw: [PmuRawCounters; MAX_PMU_ENTRIES],
+    /// `raw` 中有效的条目数。
+    pub raw_len: usize,
+}
+
+impl PmuInfo {
+    /// 未发现 PMU 节点。
+    pub const NONE: Self = Self {
+        counters: [PmuCounters {
+            start: 0,
+            end: 0,
+            counters: 0,
+        }; MAX_PMU_ENTRIES],
+        counters_len: 0,
+        selectors: [PmuSelector {
+            event: 0,
+            selector: 0,
+        }; MAX_PMU_ENTRIES],
+        selectors_len: 0,
+        raw: [PmuRawCounters {
+            value: 0,
+            mask: 0,
+            counters: 0,
+        }; MAX_PMU_ENTRIES],
+        raw_len: 0,
+    };
+
+    /// 是否没有任何映射。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.counters_len == 0 && self.raw_len == 0
+    }
+
+    /// 所有映射中出现的计数器。
+    pub fn all_counters(&self) -> u32 {
+        let counters = self.counters[..self.counters_len]
+            .iter()
+            .map(|e| e.counters);
+        let raw = self.raw[..self.raw_len].iter().map(|e| e.counters);
+        counters.chain(raw).fold(0, |acc, c| acc | c)
+    }
+
+    /// 可以计数事件 `event` 的计数器。
+    pub fn counters_for(&self, event: u32) -> u32 {
+        self.counters[..self.counters_len]
+            .iter()
+            .filter(|e| (e.start..=e.end).contains(&event))
+            .fold(0, |acc, e| acc | e.counters)
+    }
+
+    /// 事件 `event` 对应的 `mhpmevent` 值。
+    pub fn selector_for(&self, event: u32) -> Option<u64> {
+        self.selectors[..self.selectors_len]
+            .iter()
+            .find(|e| e.event == event)
+            .map(|e| e.selector)
+    }
+
+    /// 可以计数原始事件 `value` 的计数器。
+    pub fn raw_counters_for(&self, value: u64) -> u32 {
+        self.raw[..self.raw_len]
+            .iter()
+            .filter(|e| value & e.mask == e.value)
+            .fold(0, |acc, e| acc | e.counters)
+    }
+
+    /// 解析 `riscv,pmu` 节点的一个属性，不认识的属性和超出容量的条目被忽略。
+    pub(crate) fn parse(&mut self, name: &[u8], value: &[u8]) {
+        let cell = |i: usize| be_cells(&value[4 * i..][..4]) as u32;
+        let wide = |i: usize| (cell(i) as u64) << 32 | cell(i + 1) as u64;
+        match name {
+            b"riscv,event-to-mhpmcounters" => {
+                for i in (0..value.len() / 12).map(|i| i * 3) {
+                    if self.counters_len < MAX_PMU_ENTRIES {
+                        self.counters[self.counters_len] = PmuCounters {
+                            start: cell(i),
+                            end: cell(i + 1),
+                            counters: cell(i + 2),
+                        };
+                        self.counters_len += 1;
+                    }
+                }
+            }
+            b"riscv,event-to-mhpmevent" => {
+                for i in (0..value.len() / 12).map(|i| i * 3) {
+                    if self.selectors_len < MAX_PMU_ENTRIES {
+                        self.selectors[self.selectors_len] = PmuSelector {
+                            event: cell(i),
+                            selector: wide(i + 1),
+                        };
+                        self.selectors_len += 1;
+                    }
+                }
+            }
+            b"riscv,raw-event-to-mhpmcounters" => {
+                for i in (0..value.len() / 20).map(|i| i * 5) {
+                    if self.raw_len < MAX_PMU_ENTRIES {
+                        self.raw[self.raw_len] = PmuRawCounters {
+                            value: wide(i),
+                            mask: wide(i + 2),
+                            counters: cell(i + 4),
+                        };
+                        self.raw_len += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
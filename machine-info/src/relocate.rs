@@ -0,0 +1,87 @@
+//! 设备树的位置检查和重定位。
+
+use core::ops::Range;
+
+/// 固件自有的设备树缓冲区容量。
+pub const DTB_BUFFER_SIZE: usize = 64 << 10;
+
+/// 设备树头部的魔数。
+pub(crate) const FDT_MAGIC: u32 = 0xd00d_feed;
+
+/// 设备树头部的长度。
+pub(crate) const FDT_HEADER_LEN: usize = 40;
+
+/// 定位设备树时发生的错误。
+#[derive(Debug)]
+pub enum DtbError {
+    /// 设备树指针为空。
+    Null,
+    /// 魔数不正确，附带读到的值。
+    Magic(u32),
+    /// 头部记录的总长度不合理，附带读到的值。
+    TotalSize(usize),
+    /// 设备树放不进固件缓冲区，附带需要的长度。
+    TooLarge(usize),
+    /// 设备树结构无法解析。
+    Invalid,
+}
+
+/// 固件自有的对齐缓冲区。
+#[repr(C, align(8))]
+struct Buffer([u8; DTB_BUFFER_SIZE]);
+
+static mut BUFFER: Buffer = Buffer([0; DTB_BUFFER_SIZE]);
+
+/// 检查设备树头部，返回设备树总长度。
+///
+/// 引导程序传来的地址未必对齐，所以头部按字节读取。
+pub(crate) fn total_size(ptr: usize) -> Result<usize, DtbError> {
+    if ptr == 0 {
+        return Err(DtbError::Null);
+    }
+    let magic = read_be32(ptr);
+    if magic != FDT_MAGIC {
+        return Err(DtbError::Magic(magic));
+    }
+    match read_be32(ptr + 4) as usize {
+        size if size < FDT_HEADER_LEN => Err(DtbError::TotalSize(size)),
+        size => Ok(size),
+    }
+}
+
+/// 判断设备树能否原地使用：必须 8 字节对齐，且不与任何保留范围重叠。
+pub(crate) fn usable_in_place(dtb: &Range<usize>, reserved: &[Range<usize>]) -> bool {
+    dtb.start % 8 == 0 && !reserved.iter().any(|r| overlaps(r, dtb))
+}
+
+/// 判断 `inner` 是否完全位于 `outer` 内。
+pub(crate) fn contains(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+/// 将 `size` 字节的设备树复制到固件缓冲区，返回新的地址。
+pub(crate) fn copy(ptr: usize, size: usize) -> Result<usize, DtbError> {
+    if size > DTB_BUFFER_SIZE {
+        return Err(DtbError::TooLarge(size));
+    }
+    let dst = buffer();
+    // 原位置可能与缓冲区重叠，不能用 `copy_nonoverlapping`。
+    unsafe { core::ptr::copy(ptr as *const u8, dst, size) };
+    Ok(dst as usize)
+}
+
+/// 固件缓冲区的起始地址。
+pub(crate) fn buffer() -> *mut u8 {
+    unsafe { core::ptr::addr_of_mut!(BUFFER) as *mut u8 }
+}
+
+#[inline]
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// 按大端序读取可能未对齐的 32 位数。
+#[inline]
+fn read_be32(addr: usize) -> u32 {
+    u32::from_be_bytes(unsafe { (addr as *const [u8; 4]).read_unaligned() })
+}
@@ -0,0 +1,173 @@
+//! 机器信息的格式化输出。
+//!
+//! - [`Debug`] 输出全部字段，地址以十六进制表示；
+//! - [`Display`] 输出启动时打印的表格；
+//! - [`Dump`] 逐行输出 `key=value`，供宿主机上的测试从串口输出中解析。
+
+use crate::{InlineString, MachineInfo, PlicInfo, PmuInfo, UartInfo};
+use core::{
+    fmt::{self, Debug, Display, Formatter, Write},
+    ops::Range,
+};
+
+impl Debug for MachineInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MachineInfo")
+            .field("dtb", &Hex(&self.dtb))
+            .field("model", &self.model)
+            .field("smp", &self.smp)
+            .field("isa", &self.isa)
+            .field("mem", &Hex(&self.mem))
+            .field("uart", &self.uart)
+            .field("htif", &self.htif)
+            .field("test", &Hex(&self.test))
+            .field("clint", &Hex(&self.clint))
+            .field("sswi", &Hex(&self.sswi))
+            .field("plic", &self.plic)
+            .field("pmu", &self.pmu)
+            .field("timebase_frequency", &self.timebase_frequency)
+            .finish()
+    }
+}
+
+impl Debug for UartInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UartInfo")
+            .field("model", &self.model)
+            .field("reg", &Hex(&self.reg))
+            .field("clock_frequency", &self.clock_frequency)
+            .field("reg_shift", &self.reg_shift)
+            .field("reg_io_width", &self.reg_io_width)
+            .field("irq", &self.irq)
+            .finish()
+    }
+}
+
+impl Debug for PlicInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlicInfo")
+            .field("reg", &Hex(&self.reg))
+            .field("ndev", &self.ndev)
+            .field("m_context", &self.m_context)
+            .field("s_context", &self.s_context)
+            .finish()
+    }
+}
+
+impl Debug for PmuInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PmuInfo")
+            .field("counters", &&self.counters[..self.counters_len])
+            .field("selectors", &&self.selectors[..self.selectors_len])
+            .field("raw", &&self.raw[..self.raw_len])
+            .finish()
+    }
+}
+
+impl Display for MachineInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        /// 值一列的宽度。
+        const WIDTH: usize = 48;
+
+        fn line(f: &mut Formatter<'_>) -> fmt::Result {
+            writeln!(f, "+----------+-{:-<WIDTH$}-+", "")
+        }
+        fn row(f: &mut Formatter<'_>, key: &str, value: fmt::Arguments) -> fmt::Result {
+            let mut buf = InlineString::<WIDTH>::EMPTY;
+            // 过长的值截断显示
+            let _ = buf.write_fmt(value);
+            writeln!(f, "| {key:<8} | {buf:<WIDTH$} |")
+        }
+
+        line(f)?;
+        row(f, "model", format_args!("{}", self.model))?;
+        row(f, "smp", format_args!("{}", self.smp))?;
+        row(f, "isa", format_args!("{}", self.isa))?;
+        row(f, "memory", format_args!("{}", Region(&self.mem)))?;
+        row(f, "dtb", format_args!("{}", Region(&self.dtb)))?;
+        if self.htif {
+            row(f, "console", format_args!("htif"))?;
+        }
+        row(f, "uart", format_args!("{}", Region(&self.uart.reg)))?;
+        row(f, "test", format_args!("{}", Region(&self.test)))?;
+        row(f, "clint", format_args!("{}", Region(&self.clint)))?;
+        if !self.sswi.is_empty() {
+            row(f, "sswi", format_args!("{}", Region(&self.sswi)))?;
+        }
+        row(f, "plic", format_args!("{}", Region(&self.plic.reg)))?;
+        if !self.pmu.is_empty() {
+            row(f, "pmu", format_args!("{:#x}", self.pmu.all_counters()))?;
+        }
+        row(
+            f,
+            "timebase",
+            format_args!("{} Hz", self.timebase_frequency),
+        )?;
+        line(f)
+    }
+}
+
+impl MachineInfo {
+    /// 机器可读的输出，每行一个 `machine.<key>=<value>`。
+    #[inline]
+    pub fn dump(&self) -> Dump<'_> {
+        Dump(self)
+    }
+}
+
+/// 机器信息的 `key=value` 形式，见 [`MachineInfo::dump`]。
+///
+/// 地址范围输出为 `0x<start>..0x<end>`，数值输出为十进制。
+pub struct Dump<'a>(&'a MachineInfo);
+
+impl Display for Dump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let m = self.0;
+        writeln!(f, "machine.model={}", m.model)?;
+        writeln!(f, "machine.smp={}", m.smp)?;
+        writeln!(f, "machine.isa={}", m.isa)?;
+        writeln!(f, "machine.mem={:?}", Hex(&m.mem))?;
+        writeln!(f, "machine.dtb={:?}", Hex(&m.dtb))?;
+        writeln!(f, "machine.uart={:?}", Hex(&m.uart.reg))?;
+        writeln!(f, "machine.uart.model={:?}", m.uart.model)?;
+        writeln!(f, "machine.uart.clock-frequency={}", m.uart.clock_frequency)?;
+        writeln!(f, "machine.uart.reg-shift={}", m.uart.reg_shift)?;
+        writeln!(f, "machine.uart.reg-io-width={}", m.uart.reg_io_width)?;
+        writeln!(f, "machine.uart.irq={}", m.uart.irq)?;
+        writeln!(f, "machine.htif={}", m.htif)?;
+        writeln!(f, "machine.test={:?}", Hex(&m.test))?;
+        writeln!(f, "machine.clint={:?}", Hex(&m.clint))?;
+        writeln!(f, "machine.sswi={:?}", Hex(&m.sswi))?;
+        writeln!(f, "machine.plic={:?}", Hex(&m.plic.reg))?;
+        writeln!(f, "machine.pmu.counters={:#x}", m.pmu.all_counters())?;
+        writeln!(f, "machine.timebase-frequency={}", m.timebase_frequency)
+    }
+}
+
+/// 以十六进制输出地址范围。
+struct Hex<'a>(&'a Range<usize>);
+
+impl Debug for Hex<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}..{:#x}", self.0.start, self.0.end)
+    }
+}
+
+/// 以人类可读的形式输出地址范围及其大小。
+struct Region<'a>(&'a Range<usize>);
+
+impl Display for Region<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "-");
+        }
+        let size = self.0.end - self.0.start;
+        write!(f, "{:#x}..{:#x} ", self.0.start, self.0.end)?;
+        match size.trailing_zeros() {
+            30.. => write!(f, "({} GiB)", size >> 30),
+            20.. => write!(f, "({} MiB)", size >> 20),
+            10.. => write!(f, "({} KiB)", size >> 10),
+            _ => write!(f, "({size} B)"),
+        }
+    }
+}
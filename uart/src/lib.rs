@@ -0,0 +1,10 @@
+//! 这个项目提供串口驱动。
+
+#![no_std]
+#![deny(warnings, missing_docs)]
+
+mod ns16550a;
+mod sifive;
+
+pub use ns16550a::Ns16550a;
+pub use sifive::SifiveUart;
@@ -0,0 +1,121 @@
+//! ns16550a 兼容串口。
+
+use core::hint::spin_loop;
+
+// 寄存器序号，实际偏移还要左移 `reg-shift` 位。
+const RBR: usize = 0; // 读
+const THR: usize = 0; // 写
+const DLL: usize = 0; // DLAB = 1
+const IER: usize = 1;
+const DLM: usize = 1; // DLAB = 1
+const FCR: usize = 2; // 写
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+
+const LCR_DLAB: u8 = 1 << 7;
+/// 8 位数据位，无校验，1 位停止位。
+const LCR_8N1: u8 = 0b11;
+/// 使能并清空收发 FIFO。
+const FCR_FIFO: u8 = 0b111;
+/// DTR、RTS 和 OUT2。
+const MCR_DEFAULT: u8 = 0b1011;
+/// 接收数据可用中断。
+const IER_RDA: u8 = 1 << 0;
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+
+/// ns16550a 兼容串口。
+///
+/// 寄存器布局由设备树的 `reg-shift` 和 `reg-io-width` 描述。
+pub struct Ns16550a {
+    base: usize,
+    reg_shift: u32,
+    reg_io_width: u32,
+}
+
+impl Ns16550a {
+    /// 在 `base` 处构造串口驱动。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 ns16550a 兼容串口的寄存器基地址，
+    /// `reg_io_width` 必须是 1 或 4。
+    #[inline]
+    pub const unsafe fn new(base: usize, reg_shift: u32, reg_io_width: u32) -> Self {
+        Self {
+            base,
+            reg_shift,
+            reg_io_width,
+        }
+    }
+
+    /// 寄存器基地址。
+    #[inline]
+    pub const fn base(&self) -> usize {
+        self.base
+    }
+
+    /// 初始化串口：8N1，使能 FIFO，关闭中断。
+    ///
+    /// `clock_frequency` 为 0 时不设置波特率，保留引导程序的配置。
+    pub fn init(&self, clock_frequency: u32, baud: u32) {
+        self.write(IER, 0);
+        if clock_frequency != 0 && baud != 0 {
+            let divisor = (clock_frequency + 8 * baud) / (16 * baud);
+            self.write(LCR, LCR_DLAB);
+            self.write(DLL, divisor as u8);
+            self.write(DLM, (divisor >> 8) as u8);
+        }
+        self.write(LCR, LCR_8N1);
+        self.write(FCR, FCR_FIFO);
+        self.write(MCR, MCR_DEFAULT);
+    }
+
+    /// 开关接收中断，接收 FIFO 非空时产生中断。
+    #[inline]
+    pub fn set_rx_interrupt(&self, enable: bool) {
+        self.write(IER, if enable { IER_RDA } else { 0 });
+    }
+
+    /// 等待发送保持寄存器空闲，然后发送一个字节。
+    #[inline]
+    pub fn put_char(&self, c: u8) {
+        while self.read(LSR) & LSR_THRE == 0 {
+            spin_loop();
+        }
+        self.write(THR, c);
+    }
+
+    /// 非阻塞地接收一个字节。
+    #[inline]
+    pub fn get_char(&self) -> Option<u8> {
+        if self.read(LSR) & LSR_DR != 0 {
+            Some(self.read(RBR))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn read(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << self.reg_shift);
+        unsafe {
+            match self.reg_io_width {
+                4 => (addr as *const u32).read_volatile() as u8,
+                _ => (addr as *const u8).read_volatile(),
+            }
+        }
+    }
+
+    #[inline]
+    fn write(&self, reg: usize, val: u8) {
+        let addr = self.base + (reg << self.reg_shift);
+        unsafe {
+            match self.reg_io_width {
+                4 => (addr as *mut u32).write_volatile(val as _),
+                _ => (addr as *mut u8).write_volatile(val),
+            }
+        }
+    }
+}
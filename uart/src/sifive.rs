@@ -0,0 +1,87 @@
+//! SiFive 串口，设备树兼容字符串为 `sifive,uart0`。
+
+use core::hint::spin_loop;
+
+const TXDATA: usize = 0x00;
+const RXDATA: usize = 0x04;
+const TXCTRL: usize = 0x08;
+const RXCTRL: usize = 0x0c;
+const IE: usize = 0x10;
+const DIV: usize = 0x18;
+
+/// `txdata` 的 `full` 位和 `rxdata` 的 `empty` 位。
+const FLAG: u32 = 1 << 31;
+/// `txctrl`/`rxctrl` 的使能位。
+const ENABLE: u32 = 1;
+/// `ie` 的 `rxwm` 位。`rxctrl` 的水位 `rxcnt` 为 0，即接收队列非空时产生中断。
+const IE_RXWM: u32 = 1 << 1;
+
+/// SiFive 串口。
+pub struct SifiveUart {
+    base: usize,
+}
+
+impl SifiveUart {
+    /// 在 `base` 处构造串口驱动。
+    ///
+    /// # Safety
+    ///
+    /// `base` 必须是 SiFive 串口的寄存器基地址。
+    #[inline]
+    pub const unsafe fn new(base: usize) -> Self {
+        Self { base }
+    }
+
+    /// 寄存器基地址。
+    #[inline]
+    pub const fn base(&self) -> usize {
+        self.base
+    }
+
+    /// 初始化串口：使能收发，关闭中断。
+    ///
+    /// `clock_frequency` 为 0 时不设置波特率，保留引导程序的配置。
+    pub fn init(&self, clock_frequency: u32, baud: u32) {
+        if clock_frequency != 0 && baud != 0 {
+            self.write(DIV, (clock_frequency + baud / 2) / baud - 1);
+        }
+        self.write(IE, 0);
+        self.write(TXCTRL, ENABLE);
+        self.write(RXCTRL, ENABLE);
+    }
+
+    /// 开关接收中断，接收队列非空时产生中断。
+    #[inline]
+    pub fn set_rx_interrupt(&self, enable: bool) {
+        self.write(IE, if enable { IE_RXWM } else { 0 });
+    }
+
+    /// 等待发送队列有空位，然后发送一个字节。
+    #[inline]
+    pub fn put_char(&self, c: u8) {
+        while self.read(TXDATA) & FLAG != 0 {
+            spin_loop();
+        }
+        self.write(TXDATA, c as _);
+    }
+
+    /// 非阻塞地接收一个字节。
+    #[inline]
+    pub fn get_char(&self) -> Option<u8> {
+        // 读 rxdata 会弹出接收队列，所以只能读一次
+        match self.read(RXDATA) {
+            data if data & FLAG != 0 => None,
+            data => Some(data as u8),
+        }
+    }
+
+    #[inline]
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { ((self.base + reg) as *const u32).read_volatile() }
+    }
+
+    #[inline]
+    fn write(&self, reg: usize, val: u32) {
+        unsafe { ((self.base + reg) as *mut u32).write_volatile(val) }
+    }
+}
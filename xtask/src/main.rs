@@ -138,6 +138,12 @@ struct QemuArgs {
     /// Path of executable qemu-system-x.
     #[clap(long)]
     qemu_dir: Option<String>,
+    /// Machine to emulate, `virt` or `sifive_u`.
+    #[clap(long, default_value = "virt")]
+    machine: String,
+    /// Kernel image to load at 0x80200000.
+    #[clap(long)]
+    kernel: Option<PathBuf>,
     /// Number of hart (SMP for Symmetrical Multiple Processor).
     #[clap(long)]
     smp: Option<u8>,
@@ -153,13 +159,13 @@ impl QemuArgs {
             Qemu::search_at(p);
         }
         Qemu::system("riscv64")
-            .args(&["-machine", "virt"])
+            .args(&["-machine", &self.machine])
             .arg("-nographic")
             .arg("-bios")
             .arg(objcopy(elf, true))
-            // TODO
-            // .arg("-kernel")
-            // .arg(PROJECT.join("test-kernel.bin"))
+            .optional(&self.kernel, |qemu, kernel| {
+                qemu.arg("-kernel").arg(kernel);
+            })
             .args(&["-smp", &self.smp.unwrap_or(1).to_string()])
             .args(&["-m", "64M"])
             .args(&["-serial", "mon:stdio"])